@@ -17,19 +17,161 @@
 
 #[macro_use]
 extern crate criterion;
+extern crate kvbench;
 extern crate leveldb;
 extern crate lmdb;
+extern crate lmdb_sys;
+extern crate rand;
 extern crate tempdir;
+extern crate walkdir;
 
 use criterion::{
+    Bencher,
     Criterion,
     Fun,
+    ParameterizedBenchmark,
+    Throughput,
 };
 
+use kvbench::engine::{
+    Change,
+    Engine,
+    Leveldb,
+    Lmdb,
+};
+use kvbench::multimap::{
+    LeveldbComposite,
+    LmdbDupFixed,
+    LmdbDupSort,
+    Multimap,
+};
+use kvbench::tables::{
+    LeveldbDirectories,
+    LeveldbPrefixes,
+    LmdbDatabases,
+    LmdbPrefixes,
+    Tables,
+};
+use kvbench::verify;
+use kvbench::verify::Checksums;
+
+use leveldb::database::batch::{
+    Batch,
+    Writebatch,
+};
 use leveldb::database::Database as LeveldbDatabase;
-use leveldb::options::Options;
+use leveldb::iterator::Iterable;
+use leveldb::options::{
+    Options,
+    ReadOptions,
+    WriteOptions,
+};
 use lmdb::Environment;
+use lmdb_sys::{
+    mdb_env_copy,
+    mdb_env_copy2,
+    MDB_CP_COMPACT,
+};
+use rand::{
+    random,
+    thread_rng,
+    Rng,
+};
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CString;
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
 use tempdir::TempDir;
+use walkdir::WalkDir;
+
+// The numbers of pairs in the datastores whose open times we compare.
+// These are much larger than the other benches' numbers, since startup
+// latency is dominated by the time it takes to open a large datastore.
+const OPEN_PAIR_COUNTS: [u32; 5] = [1_000, 10_000, 100_000, 1_000_000, 10_000_000];
+const OPEN_VALUE_SIZE: usize = 100;
+const OPEN_BATCH_SIZE: u32 = 10_000;
+
+// The numbers of tables across which the table benches spread their pairs,
+// and the number and size of those pairs.
+const TABLE_COUNTS: [u32; 4] = [1, 4, 16, 64];
+const TABLES_PAIR_COUNT: u32 = 1000;
+const TABLES_VALUE_SIZE: usize = 100;
+
+// The number of keys in the multimap benches, and the numbers of values
+// per key across which we parameterize them.
+const MULTIMAP_KEY_COUNT: u32 = 100;
+const MULTIMAP_VALUE_COUNTS: [u32; 4] = [1, 10, 100, 1000];
+
+// The workloads of the multimap benches.
+#[derive(Clone, Copy)]
+enum MultimapWorkload {
+    Insert,
+    Get,
+    Values,
+    Delete,
+}
+
+// The number of pairs in the large-value benches, and the sizes of values
+// across which we parameterize them, all of which are larger than an LMDB
+// page, so LMDB stores them on overflow pages.
+const LARGE_PAIR_COUNT: u32 = 4;
+const LARGE_VALUE_SIZES: [usize; 4] = [4 * 1024, 64 * 1024, 1024 * 1024, 16 * 1024 * 1024];
+
+// The numbers of pairs in the datastores that the backup benches back up,
+// before they delete half of them.
+const BACKUP_PAIR_COUNTS: [u32; 3] = [1_000, 10_000, 100_000];
+
+// The ways the backup benches back up a datastore.
+#[derive(Clone, Copy)]
+enum Backup {
+    // Copy the files of a closed LevelDB datastore.
+    LeveldbCopyDir,
+    // Iterate a LevelDB datastore, writing its pairs into a new one.
+    LeveldbIterate,
+    // Copy an LMDB environment with mdb_env_copy.
+    LmdbCopy,
+    // Copy an LMDB environment with mdb_env_copy2 and MDB_CP_COMPACT,
+    // which omits its free pages and renumbers the rest.
+    LmdbCopyCompact,
+}
+
+const BACKUPS: [(&str, Backup); 4] = [
+    ("leveldb_copy_dir", Backup::LeveldbCopyDir),
+    ("leveldb_iterate", Backup::LeveldbIterate),
+    ("lmdb_copy", Backup::LmdbCopy),
+    ("lmdb_copy_compact", Backup::LmdbCopyCompact),
+];
+
+// The workloads of the large-value benches.
+#[derive(Clone, Copy)]
+enum LargeWorkload {
+    Put,
+    Get,
+    Overwrite,
+    Delete,
+}
+
+// The amount of data one iteration of a benchmark processes, given
+// the number of pairs it processes and the size of their values, which
+// Criterion uses to report throughput.  Like the engine benches, we report
+// bytes (of keys plus values) per second by default, and elements (i.e. pairs)
+// per second if the environment variable KVBENCH_THROUGHPUT is set to "elements".
+fn throughput(num_pairs: u32, size_values: usize) -> Throughput {
+    match env::var("KVBENCH_THROUGHPUT") {
+        Ok(ref unit) if unit == "elements" => Throughput::Elements(num_pairs),
+        _ => {
+            let size_pair = mem::size_of::<u32>() + size_values;
+            Throughput::Bytes(num_pairs.saturating_mul(size_pair as u32))
+        },
+    }
+}
 
 fn cmp_open_db(c: &mut Criterion) {
     let leveldb_dir = TempDir::new("leveldb_bench_open_db").unwrap();
@@ -70,5 +212,675 @@ fn cmp_open_db(c: &mut Criterion) {
     c.bench_functions("cmp_open_db", bench_functions, ());
 }
 
-criterion_group!(benches, cmp_open_db);
+// The checksums of the values of the given pairs, for verifying
+// the datastore they were written to.
+fn get_checksums(pairs: &[(u32, Vec<u8>)]) -> Checksums {
+    let mut checksums = Checksums::new();
+    for (key, value) in pairs {
+        checksums.insert(*key, value);
+    }
+    checksums
+}
+
+// If KVBENCH_VERIFY is set, verify that both getting each key and iterating
+// the datastore return exactly the pairs whose checksums we recorded.
+fn verify_engine<E: Engine>(engine: &E, checksums: &Checksums) {
+    if !verify::enabled() {
+        return;
+    }
+
+    for &key in checksums.keys() {
+        checksums.check_value(E::NAME, key, engine.get(key).as_ref().map(|value| &value[..]));
+    }
+    checksums.check_pairs(E::NAME, engine.range(0, u32::MAX));
+}
+
+// Populate a datastore with the given number of pairs and close it cleanly,
+// returning the checksums of their values.
+fn populate_checksummed<E: Engine>(dir: &Path, num_pairs: u32) -> Checksums {
+    let engine = E::open(dir, false);
+    let mut checksums = Checksums::new();
+    let mut start = 0;
+    while start < num_pairs {
+        let end = num_pairs.min(start + OPEN_BATCH_SIZE);
+        let pairs: Vec<(u32, Vec<u8>)> =
+            (start..end).map(|key| (key, (0..OPEN_VALUE_SIZE).map(|_| random()).collect())).collect();
+        engine.write(&pairs);
+        for (key, value) in &pairs {
+            checksums.insert(*key, value);
+        }
+        start = end;
+    }
+    checksums
+}
+
+fn populate<E: Engine>(dir: &Path, num_pairs: u32) {
+    populate_checksummed::<E>(dir, num_pairs);
+}
+
+// Populate a datastore with the given number of pairs and then crash
+// without closing it, which the crash program does for us by killing
+// itself once it has populated the datastore.
+fn populate_and_crash<E: Engine>(dir: &Path, num_pairs: u32) {
+    let status = Command::new(env!("CARGO_BIN_EXE_crash"))
+        .arg("--populate")
+        .arg(E::NAME)
+        .arg(dir)
+        .arg(num_pairs.to_string())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+// Copy a datastore into a new directory, so a benchmark can open it
+// in the state it was left in, no matter what previous opens did to it
+// (like LevelDB replaying its log into a new table).  To make copying large
+// datastores cheap, we hard-link the files that opening a datastore doesn't
+// modify: LevelDB's tables, which are immutable, and LMDB's data file, which
+// it only reads when opening an environment.
+fn copy_datastore(src: &Path) -> TempDir {
+    let dst = TempDir::new("copy").unwrap();
+    for entry in fs::read_dir(src).unwrap() {
+        let path = entry.unwrap().path();
+        let target = dst.path().join(path.file_name().unwrap());
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ldb") | Some("sst") | Some("mdb") if !path.ends_with("lock.mdb") => {
+                fs::hard_link(&path, &target).unwrap()
+            },
+            _ => {
+                fs::copy(&path, &target).unwrap();
+            },
+        }
+    }
+    dst
+}
+
+// Compare the times it takes to open a datastore that has been populated
+// by a previous process, given a function that populates it.  Criterion calls
+// a benchmark's routine for every sample, so we populate a datastore
+// for each number of pairs only the first time, and each iteration opens
+// a copy of it.  We return the copy from the routine so it gets closed
+// (and deleted) outside the measured region.
+//
+// We don't declare a throughput, because opening a datastore doesn't process
+// its pairs: LMDB opens one in the same time regardless of its size, so pairs
+// per second would only reflect the number of pairs in the datastore.
+fn bench_open_populated(c: &mut Criterion, id: &str, populate_leveldb: fn(&Path, u32), populate_lmdb: fn(&Path, u32)) {
+    let mut leveldb_templates: HashMap<u32, TempDir> = HashMap::new();
+    let mut lmdb_templates: HashMap<u32, TempDir> = HashMap::new();
+    c.bench(
+        id,
+        ParameterizedBenchmark::new(
+            "leveldb",
+            move |b, num_pairs| {
+                let template = leveldb_templates.entry(*num_pairs).or_insert_with(|| {
+                    let dir = TempDir::new("leveldb_bench_open_db").unwrap();
+                    populate_leveldb(dir.path(), *num_pairs);
+                    dir
+                });
+                b.iter_with_setup(
+                    || copy_datastore(template.path()),
+                    |copy| {
+                        let db: LeveldbDatabase<i32> = LeveldbDatabase::open(copy.path(), Options::new()).unwrap();
+                        (db, copy)
+                    },
+                )
+            },
+            OPEN_PAIR_COUNTS.to_vec(),
+        ).with_function("lmdb", move |b, num_pairs| {
+            let template = lmdb_templates.entry(*num_pairs).or_insert_with(|| {
+                let dir = TempDir::new("bench_open_db").unwrap();
+                populate_lmdb(dir.path(), *num_pairs);
+                dir
+            });
+            b.iter_with_setup(
+                || copy_datastore(template.path()),
+                |copy| {
+                    let env = Environment::new().open(copy.path()).unwrap();
+                    env.open_db(None).unwrap();
+                    (env, copy)
+                },
+            )
+        }),
+    );
+}
+
+fn cmp_open_db_populated(c: &mut Criterion) {
+    bench_open_populated(c, "cmp_open_db_populated", populate::<Leveldb>, populate::<Lmdb>);
+}
+
+// This measures the time it takes to recover a datastore after a crash,
+// which for LevelDB includes replaying its log, and for LMDB includes
+// reinitializing its lock file (which records its readers).
+fn cmp_open_db_crashed(c: &mut Criterion) {
+    bench_open_populated(c, "cmp_open_db_crashed", populate_and_crash::<Leveldb>, populate_and_crash::<Lmdb>);
+}
+
+// The (table, key, value) triples that the table benches write,
+// which assign keys to tables round-robin.
+fn get_table_pairs(num_tables: u32) -> Vec<(u32, u32, Vec<u8>)> {
+    (0..TABLES_PAIR_COUNT)
+        .map(|key| (key % num_tables, key, (0..TABLES_VALUE_SIZE).map(|_| random()).collect()))
+        .collect()
+}
+
+// Benchmark a workload on a layout of a datastore whose pairs are spread
+// across the given number of tables: writing every pair, or (if `get` is true)
+// reading every pair in random order.
+fn bench_tables<T: Tables>(b: &mut Bencher, num_tables: u32, get: bool) {
+    let dir = TempDir::new("bench_tables").unwrap();
+    let tables = T::open(dir.path(), num_tables);
+    let pairs = get_table_pairs(num_tables);
+
+    if get {
+        tables.write(&pairs);
+        let mut keys: Vec<(u32, u32)> = pairs.iter().map(|&(table, key, _)| (table, key)).collect();
+        thread_rng().shuffle(&mut keys[..]);
+        b.iter(|| tables.read(&keys));
+    } else {
+        b.iter(|| tables.write(&pairs));
+    }
+
+    verify_tables(&tables, num_tables, &pairs);
+}
+
+// If KVBENCH_VERIFY is set, verify that both getting each key from its table
+// and iterating the tables return exactly the given (table, key, value) triples.
+// Keys are unique across tables, so we check the pairs in key order.
+fn verify_tables<T: Tables>(tables: &T, num_tables: u32, pairs: &[(u32, u32, Vec<u8>)]) {
+    if !verify::enabled() {
+        return;
+    }
+
+    let mut checksums = Checksums::new();
+    for (_, key, value) in pairs {
+        checksums.insert(*key, value);
+    }
+    for &(table, key, _) in pairs {
+        checksums.check_value(T::NAME, key, tables.get(table, key).as_ref().map(|value| &value[..]));
+    }
+    let mut stored: Vec<(u32, Vec<u8>)> = tables
+        .pairs()
+        .into_iter()
+        .map(|(table, key, value)| {
+            assert_eq!(table, key % num_tables, "{} iterated to key {} in the wrong table", T::NAME, key);
+            (key, value)
+        })
+        .collect();
+    stored.sort_by_key(|&(key, _)| key);
+    checksums.check_pairs(T::NAME, stored);
+}
+
+// Compare the layouts of a datastore whose pairs are spread across tables
+// (see kvbench::tables) as the number of tables grows.
+fn cmp_tables(c: &mut Criterion, id: &str, get: bool) {
+    c.bench(
+        id,
+        ParameterizedBenchmark::new(
+            LmdbDatabases::NAME,
+            move |b, num_tables| bench_tables::<LmdbDatabases>(b, *num_tables, get),
+            TABLE_COUNTS.to_vec(),
+        ).with_function(LmdbPrefixes::NAME, move |b, num_tables| bench_tables::<LmdbPrefixes>(b, *num_tables, get))
+        .with_function(LeveldbPrefixes::NAME, move |b, num_tables| bench_tables::<LeveldbPrefixes>(b, *num_tables, get))
+        .with_function(LeveldbDirectories::NAME, move |b, num_tables| {
+            bench_tables::<LeveldbDirectories>(b, *num_tables, get)
+        })
+        .throughput(|_| throughput(TABLES_PAIR_COUNT, TABLES_VALUE_SIZE)),
+    );
+}
+
+fn cmp_tables_put(c: &mut Criterion) {
+    cmp_tables(c, "cmp_tables_put", false);
+}
+
+fn cmp_tables_get(c: &mut Criterion) {
+    cmp_tables(c, "cmp_tables_get", true);
+}
+
+// The (key, value) pairs that the multimap benches insert, with the given
+// number of values per key.  Each value is a random (primary) key, so the
+// values of a key aren't inserted in order.
+fn get_multimap_pairs(values_per_key: u32) -> Vec<(u32, u32)> {
+    let mut pairs: Vec<(u32, u32)> =
+        (0..MULTIMAP_KEY_COUNT).flat_map(|key| (0..values_per_key).map(move |_| (key, random()))).collect();
+    // Random values could collide, which would leave a key with fewer values.
+    pairs.sort();
+    pairs.dedup();
+    thread_rng().shuffle(&mut pairs[..]);
+    pairs
+}
+
+fn bench_multimap<M: Multimap>(b: &mut Bencher, values_per_key: u32, workload: MultimapWorkload) {
+    let pairs = get_multimap_pairs(values_per_key);
+    let keys: Vec<u32> = (0..MULTIMAP_KEY_COUNT).collect();
+    let open = || {
+        let dir = TempDir::new("bench_multimap").unwrap();
+        let multimap = M::open(dir.path());
+        multimap.insert(&pairs);
+        (multimap, dir)
+    };
+
+    match workload {
+        MultimapWorkload::Insert => {
+            let dir = TempDir::new("bench_multimap").unwrap();
+            let multimap = M::open(dir.path());
+            b.iter(|| multimap.insert(&pairs));
+            verify_multimap(&multimap, &pairs);
+        },
+        MultimapWorkload::Get => {
+            let (multimap, _dir) = open();
+            b.iter(|| multimap.get(&keys));
+            verify_multimap(&multimap, &pairs);
+        },
+        MultimapWorkload::Values => {
+            let (multimap, _dir) = open();
+            b.iter(|| assert_eq!(multimap.values(&keys), pairs.len()));
+            verify_multimap(&multimap, &pairs);
+        },
+        // Deleting the keys leaves nothing to delete in the next iteration,
+        // so each iteration gets a freshly populated datastore, which we
+        // return from the routine so it gets closed outside the measured region.
+        MultimapWorkload::Delete => {
+            b.iter_with_setup(&open, |(multimap, dir)| {
+                multimap.delete(&keys);
+                (multimap, dir)
+            });
+            if verify::enabled() {
+                let (multimap, _dir) = open();
+                multimap.delete(&keys);
+                verify_multimap(&multimap, &[]);
+            }
+        },
+    }
+}
+
+// Group (key, value) pairs, which must be in key and then value order,
+// into each key and the concatenation of its values, so we can checksum
+// a key's values like the value of a single pair.
+fn group_multimap_pairs<I: IntoIterator<Item = (u32, u32)>>(pairs: I) -> Vec<(u32, Vec<u8>)> {
+    let mut groups: Vec<(u32, Vec<u8>)> = vec![];
+    for (key, value) in pairs {
+        match groups.last_mut() {
+            Some((last, values)) if *last == key => values.extend_from_slice(&value.to_be_bytes()),
+            _ => groups.push((key, value.to_be_bytes().to_vec())),
+        }
+    }
+    groups
+}
+
+// If KVBENCH_VERIFY is set, verify that iterating the multimap returns
+// exactly the given pairs, with each key's values in order.
+fn verify_multimap<M: Multimap>(multimap: &M, pairs: &[(u32, u32)]) {
+    if !verify::enabled() {
+        return;
+    }
+
+    let mut expected = pairs.to_vec();
+    expected.sort();
+    let checksums = get_checksums(&group_multimap_pairs(expected));
+    checksums.check_pairs(M::NAME, group_multimap_pairs(multimap.pairs()));
+}
+
+// Compare the layouts of a datastore that maps each key to many values
+// (see kvbench::multimap) as the number of values per key grows.
+fn cmp_multimap(c: &mut Criterion, id: &str, workload: MultimapWorkload) {
+    c.bench(
+        id,
+        ParameterizedBenchmark::new(
+            LmdbDupSort::NAME,
+            move |b, values_per_key| bench_multimap::<LmdbDupSort>(b, *values_per_key, workload),
+            MULTIMAP_VALUE_COUNTS.to_vec(),
+        ).with_function(LmdbDupFixed::NAME, move |b, values_per_key| {
+            bench_multimap::<LmdbDupFixed>(b, *values_per_key, workload)
+        })
+        .with_function(LeveldbComposite::NAME, move |b, values_per_key| {
+            bench_multimap::<LeveldbComposite>(b, *values_per_key, workload)
+        })
+        // Getting the first value of each key reads one value per key;
+        // the other workloads process every value.  Each value is a u32.
+        .throughput(move |values_per_key| match workload {
+            MultimapWorkload::Get => throughput(MULTIMAP_KEY_COUNT, mem::size_of::<u32>()),
+            _ => throughput(MULTIMAP_KEY_COUNT * values_per_key, mem::size_of::<u32>()),
+        }),
+    );
+}
+
+fn cmp_multimap_insert(c: &mut Criterion) {
+    cmp_multimap(c, "cmp_multimap_insert", MultimapWorkload::Insert);
+}
+
+fn cmp_multimap_get(c: &mut Criterion) {
+    cmp_multimap(c, "cmp_multimap_get", MultimapWorkload::Get);
+}
+
+fn cmp_multimap_values(c: &mut Criterion) {
+    cmp_multimap(c, "cmp_multimap_values", MultimapWorkload::Values);
+}
+
+fn cmp_multimap_delete(c: &mut Criterion) {
+    cmp_multimap(c, "cmp_multimap_delete", MultimapWorkload::Delete);
+}
+
+// The pairs that the large-value benches write, with values of the given size.
+fn get_large_pairs(size_values: usize) -> Vec<(u32, Vec<u8>)> {
+    (0..LARGE_PAIR_COUNT).map(|key| (key, (0..size_values).map(|_| random()).collect())).collect()
+}
+
+fn bench_large<E: Engine>(b: &mut Bencher, size_values: usize, workload: LargeWorkload) {
+    let pairs = get_large_pairs(size_values);
+    let keys: Vec<u32> = (0..LARGE_PAIR_COUNT).collect();
+    let open = || {
+        let dir = TempDir::new("bench_large").unwrap();
+        let engine = E::open(dir.path(), true);
+        engine.write(&pairs);
+        (engine, dir)
+    };
+
+    match workload {
+        // Each iteration gets a fresh datastore, so it writes new pairs
+        // rather than overwriting the previous iteration's.
+        LargeWorkload::Put => {
+            b.iter_with_setup(
+                || {
+                    let dir = TempDir::new("bench_large").unwrap();
+                    (E::open(dir.path(), true), dir)
+                },
+                |(engine, dir)| {
+                    engine.write(&pairs);
+                    (engine, dir)
+                },
+            );
+            if verify::enabled() {
+                let (engine, _dir) = open();
+                verify_engine(&engine, &get_checksums(&pairs));
+            }
+        },
+        LargeWorkload::Get => {
+            let (engine, _dir) = open();
+            b.iter(|| assert_eq!(engine.read(&keys), pairs.len() * size_values));
+            verify_engine(&engine, &get_checksums(&pairs));
+        },
+        // Alternate between two sets of values, so every iteration
+        // replaces each value with a different one of the same size.
+        LargeWorkload::Overwrite => {
+            let (engine, _dir) = open();
+            let other_pairs = get_large_pairs(size_values);
+            let mut other = false;
+            b.iter(|| {
+                other = !other;
+                let values = if other {
+                    &other_pairs
+                } else {
+                    &pairs
+                };
+                engine.write(values)
+            });
+            // The datastore holds whichever values the last iteration wrote.
+            let values = if other {
+                &other_pairs
+            } else {
+                &pairs
+            };
+            verify_engine(&engine, &get_checksums(values));
+        },
+        // Deleting the keys leaves nothing to delete in the next iteration,
+        // so each iteration gets a freshly populated datastore.
+        LargeWorkload::Delete => {
+            let changes: Vec<Change> = keys.iter().map(|&key| Change::Delete(key)).collect();
+            b.iter_with_setup(&open, |(engine, dir)| {
+                engine.apply(&changes);
+                (engine, dir)
+            });
+            if verify::enabled() {
+                let (engine, _dir) = open();
+                engine.apply(&changes);
+                verify_engine(&engine, &Checksums::new());
+            }
+        },
+    }
+}
+
+// Compare the engines' performance with values of the sizes of attachments
+// and other blobs, which LMDB stores on overflow pages, and which LevelDB
+// writes to its log and then rewrites into tables as it compacts them.
+fn cmp_large(c: &mut Criterion, id: &str, workload: LargeWorkload) {
+    c.bench(
+        id,
+        ParameterizedBenchmark::new(
+            Leveldb::NAME,
+            move |b, size_values| bench_large::<Leveldb>(b, *size_values, workload),
+            LARGE_VALUE_SIZES.to_vec(),
+        )
+        .with_function(Lmdb::NAME, move |b, size_values| bench_large::<Lmdb>(b, *size_values, workload))
+        .throughput(|size_values| throughput(LARGE_PAIR_COUNT, *size_values)),
+    );
+}
+
+fn cmp_large_put(c: &mut Criterion) {
+    cmp_large(c, "cmp_large_put", LargeWorkload::Put);
+}
+
+fn cmp_large_get(c: &mut Criterion) {
+    cmp_large(c, "cmp_large_get", LargeWorkload::Get);
+}
+
+fn cmp_large_overwrite(c: &mut Criterion) {
+    cmp_large(c, "cmp_large_overwrite", LargeWorkload::Overwrite);
+}
+
+fn cmp_large_delete(c: &mut Criterion) {
+    cmp_large(c, "cmp_large_delete", LargeWorkload::Delete);
+}
+
+// The total size of the files in the given directory.
+fn dir_size(path: &Path) -> u64 {
+    let mut total_size = 0;
+    for entry in WalkDir::new(path) {
+        let metadata = entry.unwrap().metadata().unwrap();
+        if metadata.is_file() {
+            total_size += metadata.len();
+        }
+    }
+    total_size
+}
+
+// Like the db_size benches, this measures space on disk, not time,
+// converting the size of a datastore populated with large values
+// into the time it takes the benchmark to complete.
+fn cmp_large_size(c: &mut Criterion) {
+    fn bench_size<E: Engine>(b: &mut Bencher, size_values: usize) {
+        let dir = TempDir::new("bench_large").unwrap();
+        let pairs = get_large_pairs(size_values);
+        let engine = E::open(dir.path(), true);
+        engine.write(&pairs);
+        verify_engine(&engine, &get_checksums(&pairs));
+        drop(engine);
+        let total_size = dir_size(dir.path());
+
+        b.iter(|| thread::sleep(Duration::from_nanos(total_size)))
+    }
+
+    c.bench(
+        "cmp_large_size",
+        ParameterizedBenchmark::new(
+            Leveldb::NAME,
+            |b, size_values| bench_size::<Leveldb>(b, *size_values),
+            LARGE_VALUE_SIZES.to_vec(),
+        )
+        .with_function(Lmdb::NAME, |b, size_values| bench_size::<Lmdb>(b, *size_values)),
+    );
+}
+
+// Populate a datastore with the given number of pairs and then delete
+// the upper half of them, which leaves LMDB with free pages that a compacting
+// copy omits, and LevelDB with deletion markers that it hasn't compacted yet.
+// Returns the checksums of the pairs that remain.
+fn populate_churned<E: Engine>(dir: &Path, num_pairs: u32) -> Checksums {
+    let mut checksums = populate_checksummed::<E>(dir, num_pairs);
+    let changes: Vec<Change> = (num_pairs / 2..num_pairs).map(Change::Delete).collect();
+    E::open(dir, false).apply(&changes);
+    for key in num_pairs / 2..num_pairs {
+        checksums.remove(key);
+    }
+    checksums
+}
+
+// A function that backs up a datastore into the given (empty) directory.
+type BackupFn = Box<dyn Fn(&Path)>;
+
+// Populate a datastore in the given directory and return a function that backs
+// it up into another (empty) directory in the given way, along with
+// the checksums of the pairs the backup should contain.  The function holds
+// the datastore open if the backup reads it through its engine.
+fn backup_source(dir: &Path, num_pairs: u32, backup: Backup) -> (BackupFn, Checksums) {
+    match backup {
+        Backup::LeveldbCopyDir => {
+            let checksums = populate_churned::<Leveldb>(dir, num_pairs);
+            let src = dir.to_path_buf();
+            let copy_dir = Box::new(move |dst: &Path| {
+                for entry in fs::read_dir(&src).unwrap() {
+                    let path = entry.unwrap().path();
+                    fs::copy(&path, dst.join(path.file_name().unwrap())).unwrap();
+                }
+            });
+            (copy_dir, checksums)
+        },
+        Backup::LeveldbIterate => {
+            let checksums = populate_churned::<Leveldb>(dir, num_pairs);
+            let db: LeveldbDatabase<i32> = LeveldbDatabase::open(dir, Options::new()).unwrap();
+            let iterate = Box::new(move |dst: &Path| {
+                let mut options = Options::new();
+                options.create_if_missing = true;
+                let copy: LeveldbDatabase<i32> = LeveldbDatabase::open(dst, options).unwrap();
+                let mut batch = Writebatch::new();
+                let mut batch_size = 0;
+                for (key, value) in db.iter(ReadOptions::new()) {
+                    batch.put(key, &value);
+                    batch_size += 1;
+                    if batch_size == OPEN_BATCH_SIZE {
+                        copy.write(WriteOptions::new(), &batch).unwrap();
+                        batch = Writebatch::new();
+                        batch_size = 0;
+                    }
+                }
+                copy.write(WriteOptions::new(), &batch).unwrap();
+            });
+            (iterate, checksums)
+        },
+        Backup::LmdbCopy | Backup::LmdbCopyCompact => {
+            let checksums = populate_churned::<Lmdb>(dir, num_pairs);
+            let env = Environment::new().open(dir).unwrap();
+            let copy = Box::new(move |dst: &Path| {
+                let path = CString::new(dst.to_str().unwrap()).unwrap();
+                let result = unsafe {
+                    match backup {
+                        Backup::LmdbCopyCompact => mdb_env_copy2(env.env(), path.as_ptr(), MDB_CP_COMPACT),
+                        _ => mdb_env_copy(env.env(), path.as_ptr()),
+                    }
+                };
+                assert_eq!(result, 0, "{}", lmdb::Error::from_err_code(result));
+            });
+            (copy, checksums)
+        },
+    }
+}
+
+// If KVBENCH_VERIFY is set, verify that a backup contains exactly the pairs
+// whose checksums we recorded.
+fn verify_backup(dst: &Path, backup: Backup, checksums: &Checksums) {
+    match backup {
+        Backup::LeveldbCopyDir | Backup::LeveldbIterate => verify_engine(&Leveldb::open(dst, true), checksums),
+        Backup::LmdbCopy | Backup::LmdbCopyCompact => verify_engine(&Lmdb::open(dst, true), checksums),
+    }
+}
+
+// Compare the times it takes to back up a datastore in each of the ways
+// that the engines support.  Each iteration backs it up into a new directory,
+// which we return from the routine so it gets deleted outside the measured
+// region.
+fn cmp_backup(c: &mut Criterion) {
+    fn bench_backup(b: &mut Bencher, num_pairs: u32, backup: Backup) {
+        let dir = TempDir::new("bench_backup").unwrap();
+        let (backup_to, checksums) = backup_source(dir.path(), num_pairs, backup);
+        b.iter_with_setup(
+            || TempDir::new("backup").unwrap(),
+            |dst| {
+                backup_to(dst.path());
+                dst
+            },
+        );
+
+        if verify::enabled() {
+            let dst = TempDir::new("backup").unwrap();
+            backup_to(dst.path());
+            verify_backup(dst.path(), backup, &checksums);
+        }
+    }
+
+    let (name, first) = BACKUPS[0];
+    let mut benchmark = ParameterizedBenchmark::new(
+        name,
+        move |b, num_pairs| bench_backup(b, *num_pairs, first),
+        BACKUP_PAIR_COUNTS.to_vec(),
+    );
+    for &(name, backup) in &BACKUPS[1..] {
+        benchmark = benchmark.with_function(name, move |b, num_pairs| bench_backup(b, *num_pairs, backup));
+    }
+    // A backup contains the half of the pairs that we didn't delete.
+    c.bench("cmp_backup", benchmark.throughput(|num_pairs| throughput(num_pairs / 2, OPEN_VALUE_SIZE)));
+}
+
+// Like the db_size benches, this measures space on disk, not time,
+// converting the size of a backup into the time it takes the benchmark
+// to complete.
+fn cmp_backup_size(c: &mut Criterion) {
+    fn bench_size(b: &mut Bencher, num_pairs: u32, backup: Backup) {
+        let dir = TempDir::new("bench_backup").unwrap();
+        let dst = TempDir::new("backup").unwrap();
+        let (backup_to, checksums) = backup_source(dir.path(), num_pairs, backup);
+        backup_to(dst.path());
+        let total_size = dir_size(dst.path());
+
+        // The backup should contain the pairs we didn't delete.
+        let count = match backup {
+            Backup::LeveldbCopyDir | Backup::LeveldbIterate => Leveldb::open(dst.path(), true).scan(),
+            Backup::LmdbCopy | Backup::LmdbCopyCompact => Lmdb::open(dst.path(), true).scan(),
+        };
+        assert_eq!(count, num_pairs / 2);
+        verify_backup(dst.path(), backup, &checksums);
+
+        b.iter(|| thread::sleep(Duration::from_nanos(total_size)))
+    }
+
+    let (name, first) = BACKUPS[0];
+    let mut benchmark = ParameterizedBenchmark::new(
+        name,
+        move |b, num_pairs| bench_size(b, *num_pairs, first),
+        BACKUP_PAIR_COUNTS.to_vec(),
+    );
+    for &(name, backup) in &BACKUPS[1..] {
+        benchmark = benchmark.with_function(name, move |b, num_pairs| bench_size(b, *num_pairs, backup));
+    }
+    c.bench("cmp_backup_size", benchmark);
+}
+
+criterion_group!(
+    benches,
+    cmp_open_db,
+    cmp_open_db_populated,
+    cmp_open_db_crashed,
+    cmp_tables_put,
+    cmp_tables_get,
+    cmp_multimap_insert,
+    cmp_multimap_get,
+    cmp_multimap_values,
+    cmp_multimap_delete,
+    cmp_large_put,
+    cmp_large_get,
+    cmp_large_overwrite,
+    cmp_large_delete,
+    cmp_large_size,
+    cmp_backup,
+    cmp_backup_size,
+);
 criterion_main!(benches);
@@ -22,12 +22,19 @@ extern crate criterion;
 extern crate lazy_static;
 
 extern crate db_key;
+extern crate kvbench;
 extern crate leveldb;
+extern crate leveldb_sys;
 extern crate rand;
 extern crate tempdir;
 extern crate walkdir;
 
-use criterion::Criterion;
+use criterion::{
+    Bencher,
+    Criterion,
+    ParameterizedBenchmark,
+    Throughput,
+};
 
 use self::rand::{
     random,
@@ -37,22 +44,61 @@ use self::rand::{
 
 use self::walkdir::WalkDir;
 
+use db_key::Key;
+
+use kvbench::page_cache;
+use kvbench::verify::{
+    self,
+    Checksums,
+};
+
 use leveldb::database::batch::{
     Batch,
     Writebatch,
 };
 
+use leveldb::compaction::Compaction;
+use leveldb::database::snapshots::Snapshots;
 use leveldb::database::Database;
 use leveldb::iterator::Iterable;
 use leveldb::kv::KV;
 
+use leveldb::database::cache::Cache;
 use leveldb::options::{
+    c_options,
+    c_readoptions,
     Options,
     ReadOptions,
     WriteOptions,
 };
 
+use leveldb_sys::{
+    leveldb_close,
+    leveldb_filterpolicy_create_bloom,
+    leveldb_filterpolicy_destroy,
+    leveldb_filterpolicy_t,
+    leveldb_free,
+    leveldb_get,
+    leveldb_open,
+    leveldb_options_destroy,
+    leveldb_options_set_filter_policy,
+    leveldb_readoptions_destroy,
+    leveldb_readoptions_t,
+    leveldb_t,
+    Compression,
+};
+
+use std::ffi::CString;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
 use std::{
+    env,
+    fmt,
     mem,
     thread,
     time,
@@ -76,18 +122,205 @@ use tempdir::TempDir;
 const PAIR_COUNTS: [u32; 3] = [1, 100, 1000];
 const VALUE_SIZES: [usize; 3] = [1, 100, 1000];
 
-#[derive(Debug)]
+// The number of times the compaction benchmarks overwrite every pair
+// and delete half of them before compacting the datastore.
+const CHURN_ROUNDS: u32 = 10;
+
+// How long the concurrent writer waits between writes.
+const WRITE_INTERVAL: time::Duration = time::Duration::from_millis(1);
+
+const KB: usize = 1024;
+const MB: usize = 1024 * KB;
+
+// A change to the default options (i.e. those of Options::new) with which
+// we open a database.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tuning {
+    Default,
+    WriteBufferSize(usize),
+    BlockSize(usize),
+    BlockRestartInterval(i32),
+    MaxOpenFiles(i32),
+    CacheSize(usize),
+    // Options::new disables compression, although LevelDB enables it by default.
+    SnappyCompression,
+    // A bloom filter with the given number of bits per key.  The leveldb crate
+    // doesn't let us set a filter policy, so only the benches that open
+    // their database through the C API (see RawDatabase) use this tuning.
+    BloomFilter(i32),
+}
+
+impl Tuning {
+    fn options(self) -> Options {
+        let mut options = Options::new();
+        options.create_if_missing = true;
+        match self {
+            Tuning::Default => {},
+            Tuning::WriteBufferSize(size) => options.write_buffer_size = Some(size),
+            Tuning::BlockSize(size) => options.block_size = Some(size),
+            Tuning::BlockRestartInterval(interval) => options.block_restart_interval = Some(interval),
+            Tuning::MaxOpenFiles(count) => options.max_open_files = Some(count),
+            Tuning::CacheSize(size) => options.cache = Some(Cache::new(size)),
+            Tuning::SnappyCompression => options.compression = Compression::Snappy,
+            // RawDatabase::open sets the filter policy.
+            Tuning::BloomFilter(_) => {},
+        }
+        options
+    }
+}
+
+// The read options with which we read a database.  ReadOptions::new verifies
+// no checksums and fills the block cache with the blocks a read reads.
+#[derive(Clone, Copy)]
+struct ReadTuning {
+    name: &'static str,
+    verify_checksums: bool,
+    fill_cache: bool,
+}
+
+const READ_TUNINGS: [ReadTuning; 4] = [
+    ReadTuning {
+        name: "default",
+        verify_checksums: false,
+        fill_cache: true,
+    },
+    ReadTuning {
+        name: "verify_checksums",
+        verify_checksums: true,
+        fill_cache: true,
+    },
+    ReadTuning {
+        name: "no_fill_cache",
+        verify_checksums: false,
+        fill_cache: false,
+    },
+    ReadTuning {
+        name: "verify_checksums_no_fill_cache",
+        verify_checksums: true,
+        fill_cache: false,
+    },
+];
+
+impl ReadTuning {
+    fn read_options<'a>(self) -> ReadOptions<'a, i32> {
+        let mut read_opts = ReadOptions::new();
+        read_opts.verify_checksums = self.verify_checksums;
+        read_opts.fill_cache = self.fill_cache;
+        read_opts
+    }
+}
+
 struct Param {
     num_pairs: u32,
     size_values: usize,
+    tuning: Tuning,
 }
 
 lazy_static! {
-    // A collection of tuples (num_pairs, size_values) representing every
-    // combination of numbers of pairs and sizes of values, which we use
-    // to benchmark storage engine performance across various shapes of data.
-    static ref PARAMS: Vec<Param> =
-        PAIR_COUNTS.iter().flat_map(|&m| VALUE_SIZES.iter().map(move |&n| Param { num_pairs: m, size_values: n })).collect();
+    // The tunings we bench each workload with.  By default, we only bench
+    // the default options.  Sweeping every tuning multiplies the time
+    // the benches take, so we only do it if the environment variable
+    // KVBENCH_LEVELDB_OPTIONS is set to "all".  LevelDB's defaults are
+    // a 4MiB write buffer, 4KiB blocks, a restart interval of 16 keys,
+    // 1000 open files, and an 8MiB cache.
+    static ref TUNINGS: Vec<Tuning> = match env::var("KVBENCH_LEVELDB_OPTIONS") {
+        Ok(ref tunings) if tunings == "all" => vec![
+            Tuning::Default,
+            Tuning::WriteBufferSize(MB),
+            Tuning::WriteBufferSize(16 * MB),
+            Tuning::BlockSize(KB),
+            Tuning::BlockSize(64 * KB),
+            Tuning::BlockRestartInterval(4),
+            Tuning::BlockRestartInterval(64),
+            Tuning::MaxOpenFiles(64),
+            Tuning::CacheSize(MB),
+            Tuning::CacheSize(64 * MB),
+            Tuning::SnappyCompression,
+        ],
+        _ => vec![Tuning::Default],
+    };
+
+    // The tunings we bench the workloads that open their database through
+    // the C API with, which include the bloom filter tunings that the others
+    // can't use.  LevelDB's documentation recommends 10 bits per key.
+    static ref RAW_TUNINGS: Vec<Tuning> = match env::var("KVBENCH_LEVELDB_OPTIONS") {
+        Ok(ref tunings) if tunings == "all" => {
+            TUNINGS.iter().cloned().chain(vec![Tuning::BloomFilter(10), Tuning::BloomFilter(20)]).collect()
+        },
+        _ => TUNINGS.clone(),
+    };
+
+    // A collection of tuples (num_pairs, size_values, tuning) representing
+    // every combination of numbers of pairs, sizes of values, and tunings,
+    // which we use to benchmark storage engine performance across various
+    // shapes of data and configurations of the database.
+    static ref PARAMS: Vec<Param> = get_params(&TUNINGS);
+
+    // The parameters of the workloads that open their database through
+    // the C API.
+    static ref RAW_PARAMS: Vec<Param> = get_params(&RAW_TUNINGS);
+}
+
+fn get_params(tunings: &[Tuning]) -> Vec<Param> {
+    tunings
+        .iter()
+        .flat_map(|&tuning| {
+            PAIR_COUNTS.iter().flat_map(move |&m| {
+                VALUE_SIZES.iter().map(move |&n| Param { num_pairs: m, size_values: n, tuning })
+            })
+        })
+        .collect()
+}
+
+// Criterion names each benchmark after the Debug representation of its
+// parameter.  We omit the tuning when it's the default, so the names
+// of those benchmarks are the same whether or not we sweep the options.
+impl fmt::Debug for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut param = f.debug_struct("Param");
+        param.field("num_pairs", &self.num_pairs).field("size_values", &self.size_values);
+        if self.tuning != Tuning::Default {
+            param.field("tuning", &self.tuning);
+        }
+        param.finish()
+    }
+}
+
+impl Param {
+    // The amount of data one iteration of a benchmark processes, which
+    // Criterion uses to report throughput.  Criterion only accepts a single
+    // throughput per benchmark, so we report bytes (of keys plus values)
+    // per second by default, from which Criterion derives MB/s, and elements
+    // (i.e. pairs) per second if the environment variable KVBENCH_THROUGHPUT
+    // is set to "elements".
+    fn throughput(&self) -> Throughput {
+        match env::var("KVBENCH_THROUGHPUT") {
+            Ok(ref unit) if unit == "elements" => Throughput::Elements(self.num_pairs),
+            _ => {
+                let size_pair = mem::size_of::<i32>() + self.size_values;
+                Throughput::Bytes(self.num_pairs.saturating_mul(size_pair as u32))
+            },
+        }
+    }
+
+    // The throughput of a benchmark that processes the pairs that remain
+    // after setup_churned_db deletes those with odd keys.
+    fn churned_throughput(&self) -> Throughput {
+        Param {
+            num_pairs: self.num_pairs.div_ceil(2),
+            ..*self
+        }
+        .throughput()
+    }
+}
+
+// Benchmark a function over every combination of PARAMS, declaring
+// the throughput of each combination.
+fn bench_over_params<F>(c: &mut Criterion, id: &str, f: F)
+where
+    F: FnMut(&mut Bencher, &&'static Param) + 'static,
+{
+    c.bench(id, ParameterizedBenchmark::new(id, f, PARAMS.iter()).throughput(|t| t.throughput()));
 }
 
 fn get_key(n: u32) -> i32 {
@@ -102,41 +335,71 @@ fn get_pair(num_pairs: u32, size_values: usize) -> (i32, Vec<u8>) {
     (get_key(num_pairs), get_value(size_values))
 }
 
-fn setup_bench_db(num_pairs: u32, size_values: usize) -> TempDir {
+// The checksums of the values of the given pairs, for verifying
+// the datastore they were written to.
+fn get_checksums(pairs: &[(i32, Vec<u8>)]) -> Checksums {
+    let mut checksums = Checksums::new();
+    for (key, value) in pairs {
+        checksums.insert(*key as u32, value);
+    }
+    checksums
+}
+
+// If KVBENCH_VERIFY is set, verify that both getting each key and iterating
+// the database return exactly the pairs whose checksums we recorded.
+fn verify_db(database: &Database<i32>, checksums: &Checksums) {
+    if !verify::enabled() {
+        return;
+    }
+
+    for &key in checksums.keys() {
+        let value = database.get(ReadOptions::new(), get_key(key)).unwrap();
+        checksums.check_value("leveldb", key, value.as_ref().map(|value| &value[..]));
+    }
+    checksums.check_pairs("leveldb", database.iter(ReadOptions::new()).map(|(key, value)| (key as u32, value)));
+}
+
+fn setup_bench_db(num_pairs: u32, size_values: usize, tuning: Tuning) -> (TempDir, Checksums) {
     let dir = TempDir::new("demo").unwrap();
 
-    let mut options = Options::new();
-    options.create_if_missing = true;
-    let database = Database::open(dir.path(), options).unwrap();
+    let database = Database::open(dir.path(), tuning.options()).unwrap();
 
     let batch = &mut Writebatch::new();
+    let mut checksums = Checksums::new();
     for i in 0..num_pairs {
-        batch.put(i as i32, &get_value(size_values));
+        let value = get_value(size_values);
+        checksums.insert(i, &value);
+        batch.put(i as i32, &value);
     }
     let mut write_opts = WriteOptions::new();
     write_opts.sync = true;
     database.write(write_opts, batch).unwrap();
 
-    dir
+    (dir, checksums)
 }
 
 fn bench_open_db(c: &mut Criterion) {
-    let dir = TempDir::new("bench_open_db").unwrap();
+    for &tuning in TUNINGS.iter() {
+        let dir = TempDir::new("bench_open_db").unwrap();
 
-    // Create the database first so we only measure the time to open
-    // an existing database.
-    {
-        let mut options = Options::new();
-        options.create_if_missing = true;
-        let _db: Database<i32> = Database::open(dir.path(), options).unwrap();
-    }
+        // Create the database first so we only measure the time to open
+        // an existing database.
+        {
+            let _db: Database<i32> = Database::open(dir.path(), tuning.options()).unwrap();
+        }
 
-    c.bench_function("leveldb_open_db", move |b| {
-        b.iter(|| {
-            let db: Database<i32> = Database::open(dir.path(), Options::new()).unwrap();
-            db
-        })
-    });
+        let id = if tuning == Tuning::Default {
+            "leveldb_open_db".to_owned()
+        } else {
+            format!("leveldb_open_db/{:?}", tuning)
+        };
+        c.bench_function(&id, move |b| {
+            b.iter(|| {
+                let db: Database<i32> = Database::open(dir.path(), tuning.options()).unwrap();
+                db
+            })
+        });
+    }
 }
 
 fn leveldb_put(db: &Database<i32>, pairs: &Vec<(i32, Vec<u8>)>, sync: bool) {
@@ -160,174 +423,743 @@ fn leveldb_put(db: &Database<i32>, pairs: &Vec<(i32, Vec<u8>)>, sync: bool) {
 }
 
 fn bench_put_seq_sync(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "leveldb_put_seq_sync",
-        |b, ref t| {
-            let Param {
-                num_pairs,
-                size_values,
-            } = t;
-            let dir = TempDir::new("bench_put_seq").unwrap();
-            let path = dir.path();
-            let mut options = Options::new();
-            options.create_if_missing = true;
-            let db: Database<i32> = Database::open(path, options).unwrap();
-            let pairs: Vec<(i32, Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
-
-            b.iter(|| leveldb_put(&db, &pairs, true))
-        },
-        PARAMS.iter(),
-    );
+    bench_over_params(c, "leveldb_put_seq_sync", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            tuning,
+        } = t;
+        let dir = TempDir::new("bench_put_seq").unwrap();
+        let path = dir.path();
+        let db: Database<i32> = Database::open(path, tuning.options()).unwrap();
+        let pairs: Vec<(i32, Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+
+        b.iter(|| leveldb_put(&db, &pairs, true));
+        verify_db(&db, &get_checksums(&pairs));
+    });
 }
 
 fn bench_put_seq_async(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "leveldb_put_seq_async",
-        |b, ref t| {
-            let Param {
-                num_pairs,
-                size_values,
-            } = t;
-            let dir = TempDir::new("bench_put_seq").unwrap();
-            let path = dir.path();
-            let mut options = Options::new();
-            options.create_if_missing = true;
-            let db: Database<i32> = Database::open(path, options).unwrap();
-            let pairs: Vec<(i32, Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
-
-            b.iter(|| leveldb_put(&db, &pairs, false))
-        },
-        PARAMS.iter(),
-    );
+    bench_over_params(c, "leveldb_put_seq_async", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            tuning,
+        } = t;
+        let dir = TempDir::new("bench_put_seq").unwrap();
+        let path = dir.path();
+        let db: Database<i32> = Database::open(path, tuning.options()).unwrap();
+        let pairs: Vec<(i32, Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+
+        b.iter(|| leveldb_put(&db, &pairs, false));
+        verify_db(&db, &get_checksums(&pairs));
+    });
 }
 
 fn bench_put_rand_sync(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "leveldb_put_rand_sync",
-        |b, ref t| {
-            let Param {
-                num_pairs,
-                size_values,
-            } = t;
-            let dir = TempDir::new("bench_put_rand_sync").unwrap();
-            let path = dir.path();
-            let mut options = Options::new();
-            options.create_if_missing = true;
-            let db: Database<i32> = Database::open(path, options).unwrap();
-            let mut pairs: Vec<(i32, Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
-            thread_rng().shuffle(&mut pairs[..]);
-
-            b.iter(|| leveldb_put(&db, &pairs, true))
-        },
-        PARAMS.iter(),
-    );
+    bench_over_params(c, "leveldb_put_rand_sync", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            tuning,
+        } = t;
+        let dir = TempDir::new("bench_put_rand_sync").unwrap();
+        let path = dir.path();
+        let db: Database<i32> = Database::open(path, tuning.options()).unwrap();
+        let mut pairs: Vec<(i32, Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+        thread_rng().shuffle(&mut pairs[..]);
+
+        b.iter(|| leveldb_put(&db, &pairs, true));
+        verify_db(&db, &get_checksums(&pairs));
+    });
 }
 
 fn bench_put_rand_async(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "leveldb_put_rand_async",
-        |b, ref t| {
-            let Param {
-                num_pairs,
-                size_values,
-            } = t;
-            let dir = TempDir::new("bench_put_rand_async").unwrap();
-            let path = dir.path();
-            let mut options = Options::new();
-            options.create_if_missing = true;
-            let db: Database<i32> = Database::open(path, options).unwrap();
-            let mut pairs: Vec<(i32, Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
-            thread_rng().shuffle(&mut pairs[..]);
-
-            b.iter(|| leveldb_put(&db, &pairs, false))
-        },
-        PARAMS.iter(),
-    );
+    bench_over_params(c, "leveldb_put_rand_async", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            tuning,
+        } = t;
+        let dir = TempDir::new("bench_put_rand_async").unwrap();
+        let path = dir.path();
+        let db: Database<i32> = Database::open(path, tuning.options()).unwrap();
+        let mut pairs: Vec<(i32, Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+        thread_rng().shuffle(&mut pairs[..]);
+
+        b.iter(|| leveldb_put(&db, &pairs, false));
+        verify_db(&db, &get_checksums(&pairs));
+    });
 }
 
 fn bench_get_seq(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "leveldb_get_seq",
-        |b, ref t| {
-            let Param {
-                num_pairs,
-                size_values,
-            } = t;
-            let dir = setup_bench_db(*num_pairs, *size_values);
-            let path = dir.path();
-            let options = Options::new();
-            let database: Database<i32> = Database::open(path, options).unwrap();
-            let keys: Vec<i32> = (0..*num_pairs as i32).collect();
+    bench_over_params(c, "leveldb_get_seq", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            tuning,
+        } = t;
+        let (dir, checksums) = setup_bench_db(*num_pairs, *size_values, *tuning);
+        let path = dir.path();
+        let database: Database<i32> = Database::open(path, tuning.options()).unwrap();
+        let keys: Vec<i32> = (0..*num_pairs as i32).collect();
 
-            b.iter(|| {
+        b.iter(|| {
+            let mut i = 0usize;
+            for key in &keys {
+                let read_opts = ReadOptions::new();
+                i += database.get(read_opts, key).unwrap().unwrap().len();
+            }
+            i
+        });
+        verify_db(&database, &checksums);
+    });
+}
+
+fn bench_get_rand(c: &mut Criterion) {
+    bench_over_params(c, "leveldb_get_rand", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            tuning,
+        } = t;
+        let (dir, checksums) = setup_bench_db(*num_pairs, *size_values, *tuning);
+        let path = dir.path();
+        let database: Database<i32> = Database::open(path, tuning.options()).unwrap();
+        let mut keys: Vec<i32> = (0..*num_pairs as i32).collect();
+        thread_rng().shuffle(&mut keys[..]);
+
+        b.iter(|| {
+            let mut i = 0usize;
+            for key in &keys {
+                let read_opts = ReadOptions::new();
+                i += database.get(read_opts, key).unwrap().unwrap().len();
+            }
+            i
+        });
+        verify_db(&database, &checksums);
+    });
+}
+
+fn bench_get_seq_iter(c: &mut Criterion) {
+    bench_over_params(c, "leveldb_get_seq_iter", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            tuning,
+        } = t;
+        let (dir, checksums) = setup_bench_db(*num_pairs, *size_values, *tuning);
+        let path = dir.path();
+        let database: Database<i32> = Database::open(path, tuning.options()).unwrap();
+        let mut keys: Vec<i32> = (0..*num_pairs as i32).collect();
+        thread_rng().shuffle(&mut keys[..]);
+
+        b.iter(|| {
+            let mut i = 0usize;
+            let mut count = 0u32;
+            let read_opts = ReadOptions::new();
+            for (key, data) in database.iter(read_opts) {
+                i += mem::size_of_val(&key) + data.len();
+                count += 1;
+            }
+            assert_eq!(count, *num_pairs);
+            i
+        });
+        verify_db(&database, &checksums);
+    });
+}
+
+// The cold-cache benchmarks close the database and evict its files from
+// the page cache before every iteration, so they measure first-touch reads
+// that go to disk.  They reopen the database before the measured region,
+// so the time to open it (and replay its log) doesn't accrue to the reads.
+fn open_cold_db(dir: &TempDir, tuning: Tuning) -> Database<i32> {
+    page_cache::evict(dir.path()).unwrap();
+    Database::open(dir.path(), tuning.options()).unwrap()
+}
+
+fn bench_get_seq_cold(c: &mut Criterion) {
+    bench_over_params(c, "leveldb_get_seq_cold", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            tuning,
+        } = t;
+        let (dir, checksums) = setup_bench_db(*num_pairs, *size_values, *tuning);
+        let keys: Vec<i32> = (0..*num_pairs as i32).collect();
+
+        // Return the database from the routine so it gets closed
+        // outside the measured region.
+        b.iter_with_setup(
+            || open_cold_db(&dir, *tuning),
+            |database| {
                 let mut i = 0usize;
                 for key in &keys {
                     let read_opts = ReadOptions::new();
                     i += database.get(read_opts, key).unwrap().unwrap().len();
                 }
-                i
-            })
-        },
-        PARAMS.iter(),
-    );
+                (database, i)
+            },
+        );
+        verify_db(&open_cold_db(&dir, *tuning), &checksums);
+    });
 }
 
-fn bench_get_rand(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "leveldb_get_rand",
-        |b, ref t| {
-            let Param {
-                num_pairs,
-                size_values,
-            } = t;
-            let dir = setup_bench_db(*num_pairs, *size_values);
-            let path = dir.path();
-            let options = Options::new();
-            let database: Database<i32> = Database::open(path, options).unwrap();
-            let mut keys: Vec<i32> = (0..*num_pairs as i32).collect();
-            thread_rng().shuffle(&mut keys[..]);
+fn bench_get_rand_cold(c: &mut Criterion) {
+    bench_over_params(c, "leveldb_get_rand_cold", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            tuning,
+        } = t;
+        let (dir, checksums) = setup_bench_db(*num_pairs, *size_values, *tuning);
+        let mut keys: Vec<i32> = (0..*num_pairs as i32).collect();
+        thread_rng().shuffle(&mut keys[..]);
 
-            b.iter(|| {
+        b.iter_with_setup(
+            || open_cold_db(&dir, *tuning),
+            |database| {
                 let mut i = 0usize;
                 for key in &keys {
                     let read_opts = ReadOptions::new();
                     i += database.get(read_opts, key).unwrap().unwrap().len();
                 }
-                i
-            })
-        },
-        PARAMS.iter(),
-    );
+                (database, i)
+            },
+        );
+        verify_db(&open_cold_db(&dir, *tuning), &checksums);
+    });
 }
 
-fn bench_get_seq_iter(c: &mut Criterion) {
+fn bench_get_seq_iter_cold(c: &mut Criterion) {
+    bench_over_params(c, "leveldb_get_seq_iter_cold", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            tuning,
+        } = t;
+        let (dir, checksums) = setup_bench_db(*num_pairs, *size_values, *tuning);
+
+        b.iter_with_setup(
+            || open_cold_db(&dir, *tuning),
+            |database| {
+                let mut i = 0usize;
+                let mut count = 0u32;
+                for (key, data) in database.iter(ReadOptions::new()) {
+                    i += mem::size_of_val(&key) + data.len();
+                    count += 1;
+                }
+                assert_eq!(count, *num_pairs);
+                (database, i)
+            },
+        );
+        verify_db(&open_cold_db(&dir, *tuning), &checksums);
+    });
+}
+
+// Call `f` while another thread rewrites each pair with its current value,
+// one pair per write, so a benchmark can measure reads under concurrent
+// modification.  Rewriting the same values keeps the datastore's contents
+// verifiable, and pausing between writes keeps a snapshot that a benchmark
+// holds from making the datastore grow too quickly.
+fn with_writer<T, F: FnOnce() -> T>(database: &Database<i32>, num_pairs: u32, f: F) -> T {
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            for key in (0..num_pairs).cycle() {
+                let value = database.get(ReadOptions::new(), get_key(key)).unwrap().unwrap();
+                database.put(WriteOptions::new(), get_key(key), &value).unwrap();
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+                thread::sleep(WRITE_INTERVAL);
+            }
+        });
+        let result = f();
+        done.store(true, Ordering::Relaxed);
+        result
+    })
+}
+
+// Benchmark of creating (and releasing) a snapshot, which a consistent read
+// of multiple pairs requires, while another thread writes to the datastore.
+fn bench_snapshot(c: &mut Criterion) {
     c.bench_function_over_inputs(
-        "leveldb_get_seq_iter",
+        "leveldb_snapshot",
         |b, ref t| {
             let Param {
                 num_pairs,
                 size_values,
+                tuning,
             } = t;
-            let dir = setup_bench_db(*num_pairs, *size_values);
-            let path = dir.path();
-            let options = Options::new();
-            let database: Database<i32> = Database::open(path, options).unwrap();
-            let mut keys: Vec<i32> = (0..*num_pairs as i32).collect();
-            thread_rng().shuffle(&mut keys[..]);
+            let (dir, checksums) = setup_bench_db(*num_pairs, *size_values, *tuning);
+            let database: Database<i32> = Database::open(dir.path(), tuning.options()).unwrap();
+
+            with_writer(&database, *num_pairs, || b.iter(|| database.snapshot()));
+            verify_db(&database, &checksums);
+        },
+        PARAMS.iter(),
+    );
+}
+
+// Get the pairs in random order while another thread writes to the datastore,
+// either reading the latest values or reading through a snapshot created
+// before the benchmark, which has to skip the newer values that the writer
+// adds as the benchmark runs.
+fn bench_get_concurrent(b: &mut Bencher, t: &Param, snapshot: bool) {
+    let Param {
+        num_pairs,
+        size_values,
+        tuning,
+    } = t;
+    let (dir, checksums) = setup_bench_db(*num_pairs, *size_values, *tuning);
+    let database: Database<i32> = Database::open(dir.path(), tuning.options()).unwrap();
+    let mut keys: Vec<i32> = (0..*num_pairs as i32).collect();
+    thread_rng().shuffle(&mut keys[..]);
 
+    with_writer(&database, *num_pairs, || {
+        if snapshot {
+            let snapshot = database.snapshot();
             b.iter(|| {
                 let mut i = 0usize;
-                let mut count = 0u32;
-                let read_opts = ReadOptions::new();
-                for (key, data) in database.iter(read_opts) {
-                    i += mem::size_of_val(&key) + data.len();
-                    count += 1;
+                for key in &keys {
+                    i += snapshot.get(ReadOptions::new(), key).unwrap().unwrap().len();
                 }
-                assert_eq!(count, *num_pairs);
                 i
             })
-        },
-        PARAMS.iter(),
+        } else {
+            b.iter(|| {
+                let mut i = 0usize;
+                for key in &keys {
+                    i += database.get(ReadOptions::new(), key).unwrap().unwrap().len();
+                }
+                i
+            })
+        }
+    });
+    verify_db(&database, &checksums);
+}
+
+fn bench_get_snapshot(c: &mut Criterion) {
+    c.bench(
+        "leveldb_get_snapshot",
+        ParameterizedBenchmark::new("read_options", |b, t| bench_get_concurrent(b, t, false), PARAMS.iter())
+            .with_function("snapshot", |b, t| bench_get_concurrent(b, t, true))
+            .throughput(|t| t.throughput()),
+    );
+}
+
+// Fill a batch with the pairs and then either write it or discard it,
+// which is how LevelDB rolls back speculative writes.  Unlike leveldb_put,
+// we always use a batch, even for a single pair.
+fn bench_batch(b: &mut Bencher, t: &Param, discard: bool) {
+    let Param {
+        num_pairs,
+        size_values,
+        tuning,
+    } = t;
+    let dir = TempDir::new("bench_batch").unwrap();
+    let db: Database<i32> = Database::open(dir.path(), tuning.options()).unwrap();
+    let pairs: Vec<(i32, Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+
+    b.iter(|| {
+        let batch = &mut Writebatch::new();
+        for (key, value) in &pairs {
+            batch.put(*key, value);
+        }
+        if !discard {
+            let mut write_opts = WriteOptions::new();
+            write_opts.sync = true;
+            db.write(write_opts, batch).unwrap();
+        }
+    });
+
+    // Discarding the batches should have left the datastore empty.
+    if discard {
+        verify_db(&db, &Checksums::new());
+    } else {
+        verify_db(&db, &get_checksums(&pairs));
+    }
+}
+
+// Benchmark of rolling back speculative writes by discarding the batch
+// that holds them, compared to writing it.
+fn bench_discard_batch(c: &mut Criterion) {
+    c.bench(
+        "leveldb_discard_batch",
+        ParameterizedBenchmark::new("write", |b, t| bench_batch(b, t, false), PARAMS.iter())
+            .with_function("discard", |b, t| bench_batch(b, t, true))
+            .throughput(|t| t.throughput()),
+    );
+}
+
+// Compare reading with each of READ_TUNINGS over the given parameters,
+// given a function that benchmarks reading with one of them.
+fn bench_over_read_tunings(
+    c: &mut Criterion,
+    id: &str,
+    params: &'static [Param],
+    f: fn(&mut Bencher, &Param, ReadTuning),
+) {
+    let first = READ_TUNINGS[0];
+    let mut benchmark = ParameterizedBenchmark::new(first.name, move |b, t| f(b, t, first), params.iter());
+    for &read in &READ_TUNINGS[1..] {
+        benchmark = benchmark.with_function(read.name, move |b, t| f(b, t, read));
+    }
+    c.bench(id, benchmark.throughput(|t| t.throughput()));
+}
+
+// Get the pairs in random order, constructing the read options for each get,
+// as leveldb_get_rand does.
+fn bench_get_read_tuning(b: &mut Bencher, t: &Param, read: ReadTuning) {
+    let Param {
+        num_pairs,
+        size_values,
+        tuning,
+    } = t;
+    let (dir, checksums) = setup_bench_db(*num_pairs, *size_values, *tuning);
+    let database: Database<i32> = Database::open(dir.path(), tuning.options()).unwrap();
+    let mut keys: Vec<i32> = (0..*num_pairs as i32).collect();
+    thread_rng().shuffle(&mut keys[..]);
+
+    b.iter(|| {
+        let mut i = 0usize;
+        for key in &keys {
+            i += database.get(read.read_options(), key).unwrap().unwrap().len();
+        }
+        i
+    });
+    verify_db(&database, &checksums);
+}
+
+// A database that we open and read through the C API rather than
+// the leveldb crate, which lets us reuse read options and set a filter policy.
+struct RawDatabase {
+    db: *mut leveldb_t,
+    // The filter policy, if any, which the database uses until it's closed.
+    policy: *mut leveldb_filterpolicy_t,
+    // The database refers to the options' cache, so they must outlive it.
+    _options: Options,
+}
+
+impl RawDatabase {
+    fn open(path: &Path, tuning: Tuning) -> RawDatabase {
+        let options = tuning.options();
+        let name = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            let c_opts = c_options(&options, None);
+            let policy = match tuning {
+                Tuning::BloomFilter(bits_per_key) => {
+                    let policy = leveldb_filterpolicy_create_bloom(bits_per_key);
+                    leveldb_options_set_filter_policy(c_opts, policy);
+                    policy
+                },
+                _ => ptr::null_mut(),
+            };
+            let mut error = ptr::null_mut();
+            let db = leveldb_open(c_opts, name.as_ptr(), &mut error);
+            leveldb_options_destroy(c_opts);
+            assert!(error.is_null());
+            RawDatabase {
+                db,
+                policy,
+                _options: options,
+            }
+        }
+    }
+
+    // Get a value the way KV::get does, but with read options that the caller
+    // created, which KV::get instead creates (and destroys) for every get.
+    unsafe fn get(&self, read_opts: *mut leveldb_readoptions_t, key: i32) -> Option<Vec<u8>> {
+        key.as_slice(|k| {
+            let mut error = ptr::null_mut();
+            let mut length = 0;
+            let result = leveldb_get(self.db, read_opts, k.as_ptr() as *const _, k.len(), &mut length, &mut error);
+            assert!(error.is_null());
+            if result.is_null() {
+                return None;
+            }
+            let value = slice::from_raw_parts(result as *const u8, length).to_vec();
+            leveldb_free(result as *mut _);
+            Some(value)
+        })
+    }
+}
+
+impl Drop for RawDatabase {
+    fn drop(&mut self) {
+        unsafe {
+            leveldb_close(self.db);
+            if !self.policy.is_null() {
+                leveldb_filterpolicy_destroy(self.policy);
+            }
+        }
+    }
+}
+
+// Get the pairs in random order, like bench_get_read_tuning, but with read
+// options that we create once, outside the measured loop.  The leveldb crate
+// doesn't let us reuse read options, since KV::get consumes them, so we open
+// the database and get its values through the C API.  Opening the database
+// writes the pairs that setup_bench_db logged to a table, with a filter
+// if the tuning specifies one.
+fn bench_get_hoisted(b: &mut Bencher, t: &Param, read: ReadTuning) {
+    let Param {
+        num_pairs,
+        size_values,
+        tuning,
+    } = t;
+    let (dir, checksums) = setup_bench_db(*num_pairs, *size_values, *tuning);
+    let mut keys: Vec<i32> = (0..*num_pairs as i32).collect();
+    thread_rng().shuffle(&mut keys[..]);
+
+    let database = RawDatabase::open(dir.path(), *tuning);
+    unsafe {
+        let read_opts = c_readoptions(&read.read_options());
+
+        b.iter(|| {
+            let mut i = 0usize;
+            for key in &keys {
+                i += database.get(read_opts, *key).unwrap().len();
+            }
+            i
+        });
+
+        leveldb_readoptions_destroy(read_opts);
+    }
+    drop(database);
+
+    verify_db(&Database::open(dir.path(), tuning.options()).unwrap(), &checksums);
+}
+
+// Iterate the pairs with the given read options.  A scan that doesn't fill
+// the cache leaves the blocks that other reads cached in it.
+fn bench_scan_read_tuning(b: &mut Bencher, t: &Param, read: ReadTuning) {
+    let Param {
+        num_pairs,
+        size_values,
+        tuning,
+    } = t;
+    let (dir, checksums) = setup_bench_db(*num_pairs, *size_values, *tuning);
+    let database: Database<i32> = Database::open(dir.path(), tuning.options()).unwrap();
+
+    b.iter(|| {
+        let mut i = 0usize;
+        let mut count = 0u32;
+        for (key, data) in database.iter(read.read_options()) {
+            i += mem::size_of_val(&key) + data.len();
+            count += 1;
+        }
+        assert_eq!(count, *num_pairs);
+        i
+    });
+    verify_db(&database, &checksums);
+}
+
+fn bench_get_read_options(c: &mut Criterion) {
+    bench_over_read_tunings(c, "leveldb_get_read_options", &PARAMS, bench_get_read_tuning);
+}
+
+fn bench_get_read_options_hoisted(c: &mut Criterion) {
+    bench_over_read_tunings(c, "leveldb_get_read_options_hoisted", &RAW_PARAMS, bench_get_hoisted);
+}
+
+// Benchmark of getting keys that don't exist, in random order, which is
+// what a bloom filter speeds up, since it lets a get skip reading the blocks
+// of the tables that don't contain the key.  A table's key range already
+// excludes keys beyond it, so we write the even keys and get the odd ones.
+// Like bench_get_hoisted, this gets them through the C API, with read options
+// that we create once.
+fn bench_get_missing(c: &mut Criterion) {
+    c.bench(
+        "leveldb_get_missing",
+        ParameterizedBenchmark::new(
+            "leveldb_get_missing",
+            |b, t| {
+                let Param {
+                    num_pairs,
+                    size_values,
+                    tuning,
+                } = t;
+                let dir = TempDir::new("bench_get_missing").unwrap();
+                {
+                    let database: Database<i32> = Database::open(dir.path(), tuning.options()).unwrap();
+                    let pairs: Vec<(i32, Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(2 * n, *size_values)).collect();
+                    leveldb_put(&database, &pairs, true);
+                }
+                let mut keys: Vec<i32> = (0..*num_pairs).map(|n| get_key(2 * n + 1)).collect();
+                thread_rng().shuffle(&mut keys[..]);
+
+                let database = RawDatabase::open(dir.path(), *tuning);
+                unsafe {
+                    let read_opts = c_readoptions(&ReadOptions::<i32>::new());
+                    b.iter(|| {
+                        for key in &keys {
+                            assert!(database.get(read_opts, *key).is_none());
+                        }
+                    });
+                    leveldb_readoptions_destroy(read_opts);
+                }
+            },
+            RAW_PARAMS.iter(),
+        )
+        // The gets return no values, so they only process keys.
+        .throughput(|t| {
+            Param {
+                size_values: 0,
+                ..**t
+            }
+            .throughput()
+        }),
+    );
+}
+
+fn bench_scan_read_options(c: &mut Criterion) {
+    bench_over_read_tunings(c, "leveldb_scan_read_options", &PARAMS, bench_scan_read_tuning);
+}
+
+// The total size of the files in the given directory.
+fn dir_size(path: &Path) -> u64 {
+    let mut total_size = 0;
+    for entry in WalkDir::new(path) {
+        let metadata = entry.unwrap().metadata().unwrap();
+        if metadata.is_file() {
+            total_size += metadata.len();
+        }
+    }
+    total_size
+}
+
+// Populate a datastore under a heavy write/delete load: in each of
+// CHURN_ROUNDS rounds, write every pair with a new value and then delete
+// the pairs with odd keys, which leaves the datastore full of overwritten
+// values and deletion markers for compaction to discard.  Returns
+// the checksums of the pairs that remain, whose keys are even.
+fn setup_churned_db(t: &Param) -> (TempDir, Database<i32>, Checksums) {
+    let Param {
+        num_pairs,
+        size_values,
+        tuning,
+    } = t;
+    let dir = TempDir::new("bench_compact").unwrap();
+    let database: Database<i32> = Database::open(dir.path(), tuning.options()).unwrap();
+    let mut pairs = vec![];
+    for _ in 0..CHURN_ROUNDS {
+        pairs = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+        leveldb_put(&database, &pairs, false);
+        let batch = &mut Writebatch::new();
+        for key in (1..*num_pairs).step_by(2) {
+            batch.delete(get_key(key));
+        }
+        database.write(WriteOptions::new(), batch).unwrap();
+    }
+    pairs.retain(|&(key, _)| key % 2 == 0);
+    (dir, database, get_checksums(&pairs))
+}
+
+// Compact the whole key space.  The database orders keys by their big-endian
+// encodings, so this range includes every nonnegative key.
+fn compact(database: &Database<i32>) {
+    database.compact(&0, &i32::MAX);
+}
+
+// Benchmark of compacting a datastore after a heavy write/delete load.
+fn bench_compact_range(c: &mut Criterion) {
+    bench_over_params(c, "leveldb_compact_range", |b, t| {
+        // Compacting the datastore leaves little for the next iteration
+        // to compact, so each iteration gets a freshly churned datastore,
+        // which we return from the routine so it gets closed outside
+        // the measured region.
+        b.iter_with_setup(
+            || setup_churned_db(t),
+            |(dir, database, checksums)| {
+                compact(&database);
+                (dir, database, checksums)
+            },
+        );
+
+        let (_dir, database, checksums) = setup_churned_db(t);
+        compact(&database);
+        verify_db(&database, &checksums);
+    });
+}
+
+// Get every key in random order, including the deleted ones, from a datastore
+// after a heavy write/delete load, either as is or after compacting it.
+fn bench_get_churned(b: &mut Bencher, t: &Param, compacted: bool) {
+    let (_dir, database, checksums) = setup_churned_db(t);
+    if compacted {
+        compact(&database);
+    }
+    let mut keys: Vec<i32> = (0..t.num_pairs as i32).collect();
+    thread_rng().shuffle(&mut keys[..]);
+
+    b.iter(|| {
+        let mut i = 0usize;
+        for key in &keys {
+            i += database.get(ReadOptions::new(), key).unwrap().map_or(0, |value| value.len());
+        }
+        i
+    });
+    verify_db(&database, &checksums);
+}
+
+// Iterate the pairs that remain in a datastore after a heavy write/delete
+// load, either as is or after compacting it.
+fn bench_scan_churned(b: &mut Bencher, t: &Param, compacted: bool) {
+    let (_dir, database, checksums) = setup_churned_db(t);
+    if compacted {
+        compact(&database);
+    }
+    let count_pairs = checksums.keys().count() as u32;
+
+    b.iter(|| {
+        let mut i = 0usize;
+        let mut count = 0u32;
+        for (key, data) in database.iter(ReadOptions::new()) {
+            i += mem::size_of_val(&key) + data.len();
+            count += 1;
+        }
+        assert_eq!(count, count_pairs);
+        i
+    });
+    verify_db(&database, &checksums);
+}
+
+fn bench_get_compacted(c: &mut Criterion) {
+    c.bench(
+        "leveldb_get_compacted",
+        ParameterizedBenchmark::new("uncompacted", |b, t| bench_get_churned(b, t, false), PARAMS.iter())
+            .with_function("compacted", |b, t| bench_get_churned(b, t, true))
+            .throughput(|t| t.throughput()),
+    );
+}
+
+fn bench_scan_compacted(c: &mut Criterion) {
+    c.bench(
+        "leveldb_scan_compacted",
+        ParameterizedBenchmark::new("uncompacted", |b, t| bench_scan_churned(b, t, false), PARAMS.iter())
+            .with_function("compacted", |b, t| bench_scan_churned(b, t, true))
+            .throughput(|t| t.churned_throughput()),
+    );
+}
+
+// Like leveldb_db_size, this measures the space on disk of a datastore
+// after a heavy write/delete load, either as is or after compacting it.
+fn bench_db_size_compacted(c: &mut Criterion) {
+    fn bench_size(b: &mut Bencher, t: &Param, compacted: bool) {
+        let (dir, database, _) = setup_churned_db(t);
+        if compacted {
+            compact(&database);
+        }
+        let total_size = dir_size(dir.path());
+        b.iter(|| thread::sleep(time::Duration::from_nanos(total_size)))
+    }
+
+    c.bench(
+        "leveldb_db_size_compacted",
+        ParameterizedBenchmark::new("uncompacted", |b, t| bench_size(b, t, false), PARAMS.iter())
+            .with_function("compacted", |b, t| bench_size(b, t, true)),
     );
 }
 
@@ -340,16 +1172,10 @@ fn bench_db_size(c: &mut Criterion) {
             let Param {
                 num_pairs,
                 size_values,
+                tuning,
             } = t;
-            let dir = setup_bench_db(*num_pairs, *size_values);
-            let mut total_size = 0;
-
-            for entry in WalkDir::new(dir.path()) {
-                let metadata = entry.unwrap().metadata().unwrap();
-                if metadata.is_file() {
-                    total_size += metadata.len();
-                }
-            }
+            let (dir, _) = setup_bench_db(*num_pairs, *size_values, *tuning);
+            let total_size = dir_size(dir.path());
 
             b.iter(|| {
                 // Convert size on disk to benchmark time by sleeping
@@ -371,6 +1197,20 @@ criterion_group!(
     bench_get_seq,
     bench_get_rand,
     bench_get_seq_iter,
+    bench_get_seq_cold,
+    bench_get_rand_cold,
+    bench_get_seq_iter_cold,
+    bench_snapshot,
+    bench_get_snapshot,
+    bench_discard_batch,
+    bench_get_read_options,
+    bench_get_read_options_hoisted,
+    bench_get_missing,
+    bench_scan_read_options,
+    bench_compact_range,
+    bench_get_compacted,
+    bench_scan_compacted,
+    bench_db_size_compacted,
     bench_db_size,
 );
 criterion_main!(benches);
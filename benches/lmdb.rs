@@ -21,6 +21,7 @@ extern crate criterion;
 #[macro_use]
 extern crate lazy_static;
 
+extern crate kvbench;
 extern crate libc;
 extern crate lmdb;
 extern crate page_size;
@@ -28,15 +29,29 @@ extern crate rand;
 extern crate tempdir;
 extern crate walkdir;
 
-use criterion::Criterion;
+use criterion::{
+    Bencher,
+    Criterion,
+    ParameterizedBenchmark,
+    Throughput,
+};
+use kvbench::map_size;
+use kvbench::page_cache;
+use kvbench::verify::{
+    self,
+    Checksums,
+};
 use libc::size_t;
 
 use lmdb::{
     Cursor,
     Database,
+    DatabaseFlags,
     Environment,
     EnvironmentBuilder,
     EnvironmentFlags,
+    Error,
+    RwTransaction,
     Transaction,
     WriteFlags,
 };
@@ -47,7 +62,13 @@ use rand::{
     Rng,
 };
 
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
 use std::{
+    env,
+    fmt,
     mem,
     thread,
     time,
@@ -58,6 +79,7 @@ use walkdir::WalkDir;
 
 // To accommodate benchmarking datastores with many pairs and large values,
 // we increase the size of the map to fit the largest data sets we bench.
+// lmdb_put grows the map if a write fills it anyway, as an application must.
 //
 // Note that mdb_set_map_size
 // <http://www.lmdb.tech/doc/group__mdb.html#gaa2506ec8dab3d969b0e609cd82e619e5>
@@ -66,9 +88,39 @@ use walkdir::WalkDir;
 // claims it's 1MiB.  The latter seems correct in my testing, since benches
 // that fail at the default size succeed when it's manually set to 10MiB.
 //
-const MB: size_t = 1024 * 1024;
+const KB: size_t = 1024;
+const MB: size_t = 1024 * KB;
 const MAP_SIZE: size_t = 5 * MB;
 
+// The size of the map with which the map-full benchmarks start, which is
+// too small for all but the smallest data sets, so their writes fill it.
+const SMALL_MAP_SIZE: size_t = 64 * 1024;
+
+// The size of the map in the benchmarks that read while another thread
+// writes.  LMDB can't reuse the pages that a long read transaction can see,
+// so the writes grow the datastore, and the map must be large enough
+// to fit them, since it isn't safe to grow the map while a transaction
+// is open.  On a 64-bit system, the map only consumes address space until
+// pages are actually written.
+const CONCURRENT_MAP_SIZE: size_t = 1024 * MB;
+
+// How long the concurrent writer waits between writes.
+const WRITE_INTERVAL: time::Duration = time::Duration::from_millis(1);
+
+// The number of values per key in the bulk-loading benchmarks
+// of DUP_SORT databases.
+const DUPS_PER_KEY: u32 = 10;
+
+// The maximum size of a value in a DUP_SORT database, which is LMDB's default
+// maximum key size.
+const MAX_DUP_SIZE: usize = 511;
+
+// The number of pairs and sizes of values with which the reserve benchmarks
+// compare writing large values, from ones that fit on a page to ones
+// that span thousands of overflow pages.
+const RESERVE_PAIR_COUNT: u32 = 4;
+const RESERVE_VALUE_SIZES: [usize; 8] = [KB, 4 * KB, 16 * KB, 64 * KB, 256 * KB, MB, 4 * MB, 16 * MB];
+
 // We parameterize benchmarks across both the number of KV pairs we write to
 // (or read from) a datastore and the sizes of the values we write (or read).
 //
@@ -85,18 +137,105 @@ const MAP_SIZE: size_t = 5 * MB;
 const PAIR_COUNTS: [u32; 3] = [1, 100, 1000];
 const VALUE_SIZES: [usize; 3] = [1, 100, 1000];
 
-#[derive(Debug)]
+#[derive(Clone)]
 struct Param {
     num_pairs: u32,
     size_values: usize,
+    flags: EnvironmentFlags,
 }
 
 lazy_static! {
-    // A collection of tuples (num_pairs, size_values) representing every
-    // combination of numbers of pairs and sizes of values, which we use
-    // to benchmark storage engine performance across various shapes of data.
-    static ref PARAMS: Vec<Param> =
-        PAIR_COUNTS.iter().flat_map(|&m| VALUE_SIZES.iter().map(move |&n| Param { num_pairs: m, size_values: n })).collect();
+    // The sets of environment flags we bench each workload with.  By default,
+    // we only bench the default flags (plus whichever flags a benchmark sets
+    // itself, like the async benchmarks' MAP_ASYNC | WRITE_MAP).  Sweeping
+    // every set multiplies the time the benches take, so we only do it
+    // if the environment variable KVBENCH_LMDB_FLAGS is set to "all".
+    static ref FLAG_SETS: Vec<EnvironmentFlags> = match env::var("KVBENCH_LMDB_FLAGS") {
+        Ok(ref sets) if sets == "all" => vec![
+            EnvironmentFlags::empty(),
+            EnvironmentFlags::NO_SYNC,
+            EnvironmentFlags::NO_META_SYNC,
+            EnvironmentFlags::NO_READAHEAD,
+            EnvironmentFlags::NO_TLS,
+            EnvironmentFlags::NO_MEM_INIT,
+            EnvironmentFlags::WRITE_MAP,
+        ],
+        _ => vec![EnvironmentFlags::empty()],
+    };
+
+    // A collection of tuples (num_pairs, size_values, flags) representing every
+    // combination of numbers of pairs, sizes of values, and sets of flags,
+    // which we use to benchmark storage engine performance across various
+    // shapes of data and configurations of the environment.
+    static ref PARAMS: Vec<Param> = FLAG_SETS
+        .iter()
+        .flat_map(|&flags| {
+            PAIR_COUNTS.iter().flat_map(move |&m| {
+                VALUE_SIZES.iter().map(move |&n| Param { num_pairs: m, size_values: n, flags })
+            })
+        })
+        .collect();
+
+    // The subset of PARAMS whose values fit in a DUP_SORT database, which
+    // stores values as keys of its sub-databases and so limits their size
+    // to the maximum key size.
+    static ref DUP_PARAMS: Vec<Param> =
+        PARAMS.iter().filter(|t| t.size_values <= MAX_DUP_SIZE).cloned().collect();
+
+    // The subset of PARAMS whose flags allow nested transactions, which LMDB
+    // doesn't support in an environment with a writeable memory map.
+    static ref NESTED_PARAMS: Vec<Param> =
+        PARAMS.iter().filter(|t| !t.flags.contains(EnvironmentFlags::WRITE_MAP)).cloned().collect();
+
+    // The combinations of the reserve benchmarks' sizes of values
+    // and sets of flags.
+    static ref RESERVE_PARAMS: Vec<Param> = FLAG_SETS
+        .iter()
+        .flat_map(|&flags| {
+            RESERVE_VALUE_SIZES.iter().map(move |&n| Param { num_pairs: RESERVE_PAIR_COUNT, size_values: n, flags })
+        })
+        .collect();
+}
+
+// Criterion names each benchmark after the Debug representation of its
+// parameter.  We omit the flags when they're the defaults, so the names
+// of those benchmarks are the same whether or not we sweep the flags.
+impl fmt::Debug for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut param = f.debug_struct("Param");
+        param.field("num_pairs", &self.num_pairs).field("size_values", &self.size_values);
+        if !self.flags.is_empty() {
+            param.field("flags", &self.flags);
+        }
+        param.finish()
+    }
+}
+
+impl Param {
+    // The amount of data one iteration of a benchmark processes, which
+    // Criterion uses to report throughput.  Criterion only accepts a single
+    // throughput per benchmark, so we report bytes (of keys plus values)
+    // per second by default, from which Criterion derives MB/s, and elements
+    // (i.e. pairs) per second if the environment variable KVBENCH_THROUGHPUT
+    // is set to "elements".
+    fn throughput(&self) -> Throughput {
+        match env::var("KVBENCH_THROUGHPUT") {
+            Ok(ref unit) if unit == "elements" => Throughput::Elements(self.num_pairs),
+            _ => {
+                let size_pair = mem::size_of::<[u8; 4]>() + self.size_values;
+                Throughput::Bytes(self.num_pairs.saturating_mul(size_pair as u32))
+            },
+        }
+    }
+}
+
+// Benchmark a function over every combination of PARAMS, declaring
+// the throughput of each combination.
+fn bench_over_params<F>(c: &mut Criterion, id: &str, f: F)
+where
+    F: FnMut(&mut Bencher, &&'static Param) + 'static,
+{
+    c.bench(id, ParameterizedBenchmark::new(id, f, PARAMS.iter()).throughput(|t| t.throughput()));
 }
 
 fn get_key(n: u32) -> [u8; 4] {
@@ -115,183 +254,661 @@ fn get_pair(num_pairs: u32, size_values: usize) -> ([u8; 4], Vec<u8>) {
     (get_key(num_pairs), get_value(size_values))
 }
 
-fn get_env() -> EnvironmentBuilder {
+fn get_env(flags: EnvironmentFlags) -> EnvironmentBuilder {
     // The map size should be a multiple of the system page size.
     assert_eq!(MAP_SIZE % page_size::get(), 0);
 
-    *Environment::new().set_map_size(MAP_SIZE)
+    *Environment::new().set_map_size(MAP_SIZE).set_flags(flags)
+}
+
+// The checksums of the values of the given pairs, for verifying
+// the datastore they were written to.
+fn get_checksums(pairs: &[([u8; 4], Vec<u8>)]) -> Checksums {
+    let mut checksums = Checksums::new();
+    for (key, value) in pairs {
+        checksums.insert(u32::from_be_bytes(*key), value);
+    }
+    checksums
 }
 
-fn setup_bench_db(num_pairs: u32, size_values: usize) -> (TempDir, Environment) {
+// If KVBENCH_VERIFY is set, verify that both getting each key and iterating
+// the database return exactly the pairs whose checksums we recorded.
+fn verify_db(env: &Environment, db: Database, checksums: &Checksums) {
+    if !verify::enabled() {
+        return;
+    }
+
+    let txn = env.begin_ro_txn().unwrap();
+    for &key in checksums.keys() {
+        checksums.check_value("lmdb", key, txn.get(db, &get_key(key)).ok());
+    }
+    let mut cursor = txn.open_ro_cursor(db).unwrap();
+    let pairs = cursor.iter().map(|(key, value)| {
+        assert_eq!(key.len(), 4, "lmdb iterated to a key of the wrong size");
+        (u32::from_be_bytes([key[0], key[1], key[2], key[3]]), value)
+    });
+    checksums.check_pairs("lmdb", pairs);
+}
+
+fn setup_bench_db(num_pairs: u32, size_values: usize, flags: EnvironmentFlags) -> (TempDir, Environment, Checksums) {
     let dir = TempDir::new("test").unwrap();
-    let env = get_env().open(dir.path()).unwrap();
+    let env = get_env(flags).open(dir.path()).unwrap();
+    let pairs: Vec<([u8; 4], Vec<u8>)> = (0..num_pairs).map(|n| get_pair(n, size_values)).collect();
 
     {
         let db = env.open_db(None).unwrap();
-        let mut txn = env.begin_rw_txn().unwrap();
-        for i in 0..num_pairs {
-            txn.put(db, &get_key(i), &get_value(size_values), WriteFlags::empty()).unwrap();
-        }
-        txn.commit().unwrap();
+        lmdb_put(&env, db, &pairs);
     }
 
-    (dir, env)
+    (dir, env, get_checksums(&pairs))
 }
 
+// Opening an environment takes about the same time regardless of the number
+// of pairs in it, so we only sweep this benchmark across FLAG_SETS, naming it
+// after the flags unless they're the defaults.
 fn bench_open_db(c: &mut Criterion) {
-    let dir = TempDir::new("bench_open_db").unwrap();
+    for &flags in FLAG_SETS.iter() {
+        let dir = TempDir::new("bench_open_db").unwrap();
 
-    // Create the database first so we only measure the time to open
-    // an existing database.
-    {
-        let env = Environment::new().open(dir.path()).unwrap();
-        let _db = env.open_db(None).unwrap();
+        // Create the database first so we only measure the time to open
+        // an existing database.
+        {
+            let env = Environment::new().set_flags(flags).open(dir.path()).unwrap();
+            let _db = env.open_db(None).unwrap();
+        }
+
+        let id = if flags.is_empty() {
+            "lmdb_open_db".to_owned()
+        } else {
+            format!("lmdb_open_db/{:?}", flags)
+        };
+        c.bench_function(&id, move |b| {
+            b.iter(|| {
+                let env = Environment::new().set_flags(flags).open(dir.path()).unwrap();
+                let _db = env.open_db(None).unwrap();
+            })
+        });
     }
+}
+
+// Put the pairs in a single transaction, growing the map and retrying
+// the transaction if the map fills up.
+fn lmdb_put(env: &Environment, db: Database, pairs: &[([u8; 4], Vec<u8>)]) {
+    lmdb_put_with_flags(env, db, pairs, WriteFlags::empty())
+}
+
+fn lmdb_put_with_flags(env: &Environment, db: Database, pairs: &[([u8; 4], Vec<u8>)], flags: WriteFlags) {
+    map_size::write(env, |txn| {
+        for (key, value) in pairs {
+            txn.put(db, key, value, flags)?;
+        }
+        Ok(())
+    });
+}
+
+fn bench_put_seq_sync(c: &mut Criterion) {
+    bench_over_params(c, "lmdb_put_seq_sync", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            flags,
+        } = t;
+        let dir = TempDir::new("test").unwrap();
+        let env = get_env(*flags).open(dir.path()).unwrap();
+        let db = env.open_db(None).unwrap();
+        let pairs: Vec<([u8; 4], Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+
+        b.iter(|| lmdb_put(&env, db, &pairs));
+        verify_db(&env, db, &get_checksums(&pairs));
+    });
+}
+
+fn bench_put_seq_async(c: &mut Criterion) {
+    bench_over_params(c, "lmdb_put_seq_async", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            flags,
+        } = t;
+        let dir = TempDir::new("test").unwrap();
+        // LMDB writes are sync by default.  Set the MAP_ASYNC and WRITE_MAP
+        // environment flags to make them async (along with using a writeable
+        // memory map).
+        let env = get_env(*flags | EnvironmentFlags::MAP_ASYNC | EnvironmentFlags::WRITE_MAP).open(dir.path()).unwrap();
+        let db = env.open_db(None).unwrap();
+        let pairs: Vec<([u8; 4], Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+
+        b.iter(|| lmdb_put(&env, db, &pairs));
+        verify_db(&env, db, &get_checksums(&pairs));
+    });
+}
+
+fn bench_put_rand_sync(c: &mut Criterion) {
+    bench_over_params(c, "lmdb_put_rand_sync", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            flags,
+        } = t;
+        let dir = TempDir::new("test").unwrap();
+        let env = get_env(*flags).open(dir.path()).unwrap();
+        let db = env.open_db(None).unwrap();
+        let mut pairs: Vec<([u8; 4], Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+        thread_rng().shuffle(&mut pairs[..]);
+
+        b.iter(|| lmdb_put(&env, db, &pairs));
+        verify_db(&env, db, &get_checksums(&pairs));
+    });
+}
+
+fn bench_put_rand_async(c: &mut Criterion) {
+    bench_over_params(c, "lmdb_put_rand_async", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            flags,
+        } = t;
+        let dir = TempDir::new("test").unwrap();
+        // LMDB writes are sync by default.  Set the MAP_ASYNC and WRITE_MAP
+        // environment flags to make them async (along with using a writeable
+        // memory map).
+        let env = get_env(*flags | EnvironmentFlags::MAP_ASYNC | EnvironmentFlags::WRITE_MAP).open(dir.path()).unwrap();
+        let db = env.open_db(None).unwrap();
+        let mut pairs: Vec<([u8; 4], Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+        thread_rng().shuffle(&mut pairs[..]);
+
+        b.iter(|| lmdb_put(&env, db, &pairs));
+        verify_db(&env, db, &get_checksums(&pairs));
+    });
+}
+
+fn bench_get_seq(c: &mut Criterion) {
+    bench_over_params(c, "lmdb_get_seq", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            flags,
+        } = t;
+        let (_dir, env, checksums) = setup_bench_db(*num_pairs, *size_values, *flags);
+        let db = env.open_db(None).unwrap();
+        let keys: Vec<[u8; 4]> = (0..*num_pairs).map(|n| get_key(n)).collect();
 
-    c.bench_function("lmdb_open_db", move |b| {
         b.iter(|| {
-            let env = Environment::new().open(dir.path()).unwrap();
-            let _db = env.open_db(None).unwrap();
-        })
+            let txn = env.begin_ro_txn().unwrap();
+            let mut i = 0usize;
+            for key in &keys {
+                i += txn.get(db, key).unwrap().len();
+            }
+            i
+        });
+        verify_db(&env, db, &checksums);
     });
 }
 
-fn lmdb_put(env: &Environment, db: Database, pairs: &Vec<([u8; 4], Vec<u8>)>) {
-    let mut txn = env.begin_rw_txn().unwrap();
-    for (key, value) in pairs {
-        txn.put(db, key, value, WriteFlags::empty()).unwrap();
-    }
-    txn.commit().unwrap();
+fn bench_get_rand(c: &mut Criterion) {
+    bench_over_params(c, "lmdb_get_rand", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            flags,
+        } = t;
+        let (_dir, env, checksums) = setup_bench_db(*num_pairs, *size_values, *flags);
+        let db = env.open_db(None).unwrap();
+        let mut keys: Vec<[u8; 4]> = (0..*num_pairs).map(|n| get_key(n)).collect();
+        thread_rng().shuffle(&mut keys[..]);
+
+        b.iter(|| {
+            let txn = env.begin_ro_txn().unwrap();
+            let mut i = 0usize;
+            for key in &keys {
+                i += txn.get(db, key).unwrap().len();
+            }
+            i
+        });
+        verify_db(&env, db, &checksums);
+    });
 }
 
-fn bench_put_seq_sync(c: &mut Criterion) {
+/// Benchmark of iterator sequential read performance.
+fn bench_get_seq_iter(c: &mut Criterion) {
+    bench_over_params(c, "lmdb_get_seq_iter", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            flags,
+        } = t;
+        let (_dir, env, checksums) = setup_bench_db(*num_pairs, *size_values, *flags);
+        let db = env.open_db(None).unwrap();
+
+        b.iter(|| {
+            let txn = env.begin_ro_txn().unwrap();
+            let mut cursor = txn.open_ro_cursor(db).unwrap();
+            let mut i = 0usize;
+            let mut count = 0u32;
+            for (key, data) in cursor.iter() {
+                i += mem::size_of_val(&key) + data.len();
+                count += 1;
+            }
+            assert_eq!(count, *num_pairs);
+            i
+        });
+        verify_db(&env, db, &checksums);
+    });
+}
+
+// The cold-cache benchmarks close the environment and evict its files from
+// the page cache before every iteration, so they measure first-touch reads
+// that go to disk.  They reopen the environment before the measured region,
+// so the time to open it doesn't accrue to the reads.
+fn open_cold_env(dir: &TempDir, flags: EnvironmentFlags) -> (Environment, Database) {
+    page_cache::evict(dir.path()).unwrap();
+    let env = get_env(flags).open(dir.path()).unwrap();
+    let db = env.open_db(None).unwrap();
+    (env, db)
+}
+
+fn bench_get_seq_cold(c: &mut Criterion) {
+    bench_over_params(c, "lmdb_get_seq_cold", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            flags,
+        } = t;
+        let (dir, _, checksums) = setup_bench_db(*num_pairs, *size_values, *flags);
+        let keys: Vec<[u8; 4]> = (0..*num_pairs).map(get_key).collect();
+
+        // Return the environment from the routine so it gets closed
+        // outside the measured region.
+        b.iter_with_setup(
+            || open_cold_env(&dir, *flags),
+            |(env, db)| {
+                let mut i = 0usize;
+                {
+                    let txn = env.begin_ro_txn().unwrap();
+                    for key in &keys {
+                        i += txn.get(db, key).unwrap().len();
+                    }
+                }
+                (env, i)
+            },
+        );
+        let (env, db) = open_cold_env(&dir, *flags);
+        verify_db(&env, db, &checksums);
+    });
+}
+
+fn bench_get_rand_cold(c: &mut Criterion) {
+    bench_over_params(c, "lmdb_get_rand_cold", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            flags,
+        } = t;
+        let (dir, _, checksums) = setup_bench_db(*num_pairs, *size_values, *flags);
+        let mut keys: Vec<[u8; 4]> = (0..*num_pairs).map(get_key).collect();
+        thread_rng().shuffle(&mut keys[..]);
+
+        b.iter_with_setup(
+            || open_cold_env(&dir, *flags),
+            |(env, db)| {
+                let mut i = 0usize;
+                {
+                    let txn = env.begin_ro_txn().unwrap();
+                    for key in &keys {
+                        i += txn.get(db, key).unwrap().len();
+                    }
+                }
+                (env, i)
+            },
+        );
+        let (env, db) = open_cold_env(&dir, *flags);
+        verify_db(&env, db, &checksums);
+    });
+}
+
+fn bench_get_seq_iter_cold(c: &mut Criterion) {
+    bench_over_params(c, "lmdb_get_seq_iter_cold", |b, t| {
+        let Param {
+            num_pairs,
+            size_values,
+            flags,
+        } = t;
+        let (dir, _, checksums) = setup_bench_db(*num_pairs, *size_values, *flags);
+
+        b.iter_with_setup(
+            || open_cold_env(&dir, *flags),
+            |(env, db)| {
+                let mut i = 0usize;
+                let mut count = 0u32;
+                {
+                    let txn = env.begin_ro_txn().unwrap();
+                    let mut cursor = txn.open_ro_cursor(db).unwrap();
+                    for (key, data) in cursor.iter() {
+                        i += key.len() + data.len();
+                        count += 1;
+                    }
+                }
+                assert_eq!(count, *num_pairs);
+                (env, i)
+            },
+        );
+        let (env, db) = open_cold_env(&dir, *flags);
+        verify_db(&env, db, &checksums);
+    });
+}
+
+// Benchmark of resizing the map of a populated environment, which LMDB does
+// by unmapping and remapping its file.  We alternate between two sizes,
+// so the map doesn't grow without bound across iterations.  Each iteration
+// resizes the map once rather than processing the pairs, so we don't declare
+// a throughput.
+fn bench_map_resize(c: &mut Criterion) {
     c.bench_function_over_inputs(
-        "lmdb_put_seq_sync",
-        |b, ref t| {
+        "lmdb_map_resize",
+        |b, t| {
             let Param {
                 num_pairs,
                 size_values,
+                flags,
             } = t;
-            let dir = TempDir::new("test").unwrap();
-            let env = get_env().open(dir.path()).unwrap();
-            let db = env.open_db(None).unwrap();
-            let pairs: Vec<([u8; 4], Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+            let (_dir, env, checksums) = setup_bench_db(*num_pairs, *size_values, *flags);
+            let sizes = [map_size::get(&env) * 2, map_size::get(&env)];
+            let mut i = 0;
 
-            b.iter(|| lmdb_put(&env, db, &pairs))
+            b.iter(|| {
+                map_size::set(&env, sizes[i % 2]).unwrap();
+                i += 1;
+            });
+            verify_db(&env, env.open_db(None).unwrap(), &checksums);
         },
         PARAMS.iter(),
     );
 }
 
-fn bench_put_seq_async(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "lmdb_put_seq_async",
-        |b, ref t| {
-            let Param {
-                num_pairs,
-                size_values,
-            } = t;
-            let dir = TempDir::new("test").unwrap();
-            // LMDB writes are sync by default.  Set the MAP_ASYNC and WRITE_MAP
-            // environment flags to make them async (along with using a writeable
-            // memory map).
-            let env = get_env()
-                .set_flags(EnvironmentFlags::MAP_ASYNC | EnvironmentFlags::WRITE_MAP)
-                .open(dir.path())
-                .unwrap();
-            let db = env.open_db(None).unwrap();
-            let pairs: Vec<([u8; 4], Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+// Put the pairs into a new environment whose map starts at the given size.
+// Each iteration gets a fresh environment, so the first write always
+// has to grow a small map, whereas with a reused one only the first
+// iteration would.
+fn bench_put_fresh(b: &mut Bencher, t: &Param, initial_map_size: size_t) {
+    let Param {
+        num_pairs,
+        size_values,
+        flags,
+    } = t;
+    let pairs: Vec<([u8; 4], Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+    let open = || {
+        let dir = TempDir::new("test").unwrap();
+        let env = get_env(*flags).set_map_size(initial_map_size).open(dir.path()).unwrap();
+        let db = env.open_db(None).unwrap();
+        (env, db, dir)
+    };
 
-            b.iter(|| lmdb_put(&env, db, &pairs))
-        },
-        PARAMS.iter(),
+    // Return the environment from the routine so it gets closed
+    // outside the measured region.
+    b.iter_with_setup(&open, |(env, db, dir)| {
+        lmdb_put(&env, db, &pairs);
+        (env, dir)
+    });
+
+    let (env, db, _dir) = open();
+    lmdb_put(&env, db, &pairs);
+    verify_db(&env, db, &get_checksums(&pairs));
+}
+
+// Benchmark of writing to an environment whose map is too small
+// for the data ("grow"), so the write fills the map and has to grow it
+// and retry (perhaps several times), compared to writing to one whose map
+// is large enough ("presized").
+fn bench_put_map_full(c: &mut Criterion) {
+    c.bench(
+        "lmdb_put_map_full",
+        ParameterizedBenchmark::new("grow", |b, t| bench_put_fresh(b, t, SMALL_MAP_SIZE), PARAMS.iter())
+            .with_function("presized", |b, t| bench_put_fresh(b, t, MAP_SIZE))
+            .throughput(|t| t.throughput()),
     );
 }
 
-fn bench_put_rand_sync(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "lmdb_put_rand_sync",
-        |b, ref t| {
-            let Param {
-                num_pairs,
-                size_values,
-            } = t;
-            let dir = TempDir::new("test").unwrap();
-            let env = get_env().open(dir.path()).unwrap();
-            let db = env.open_db(None).unwrap();
-            let mut pairs: Vec<([u8; 4], Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
-            thread_rng().shuffle(&mut pairs[..]);
+// The pairs that the bulk-loading benchmarks load into a database with
+// the given flags, in the order in which the database sorts them.
+// INTEGER_KEY databases compare keys as native-endian integers, so we encode
+// their keys in native byte order rather than with get_key.  In DUP_SORT
+// databases, we give each key DUPS_PER_KEY values, each of which starts with
+// its index among the values of its key, so they're distinct and sorted.
+fn get_load_pairs(num_pairs: u32, size_values: usize, db_flags: DatabaseFlags) -> Vec<([u8; 4], Vec<u8>)> {
+    (0..num_pairs)
+        .map(|n| {
+            if db_flags.contains(DatabaseFlags::DUP_SORT) {
+                let mut value = get_value(size_values);
+                value[0] = (n % DUPS_PER_KEY) as u8;
+                (get_key(n / DUPS_PER_KEY), value)
+            } else if db_flags.contains(DatabaseFlags::INTEGER_KEY) {
+                (n.to_ne_bytes(), get_value(size_values))
+            } else {
+                get_pair(n, size_values)
+            }
+        })
+        .collect()
+}
 
-            b.iter(|| lmdb_put(&env, db, &pairs))
-        },
-        PARAMS.iter(),
+// Load the pairs, in sorted order, into a new database with the given flags,
+// putting them with the given write flags.  Each iteration gets a fresh
+// environment, since APPEND fails for keys that aren't greater than
+// the last key in the database.
+fn bench_load(b: &mut Bencher, t: &Param, db_flags: DatabaseFlags, write_flags: WriteFlags) {
+    let Param {
+        num_pairs,
+        size_values,
+        flags,
+    } = t;
+    let pairs = get_load_pairs(*num_pairs, *size_values, db_flags);
+    let open = || {
+        let dir = TempDir::new("test").unwrap();
+        let env = get_env(*flags).open(dir.path()).unwrap();
+        let db = env.create_db(None, db_flags).unwrap();
+        (env, db, dir)
+    };
+
+    // Return the environment from the routine so it gets closed
+    // outside the measured region.
+    b.iter_with_setup(&open, |(env, db, dir)| {
+        lmdb_put_with_flags(&env, db, &pairs, write_flags);
+        (env, dir)
+    });
+
+    // The database should contain exactly the pairs we loaded, in order.
+    if verify::enabled() {
+        let (env, db, _dir) = open();
+        lmdb_put_with_flags(&env, db, &pairs, write_flags);
+        let txn = env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(db).unwrap();
+        let loaded: Vec<(&[u8], &[u8])> = cursor.iter().collect();
+        let expected: Vec<(&[u8], &[u8])> = pairs.iter().map(|(key, value)| (&key[..], &value[..])).collect();
+        assert!(loaded == expected, "lmdb loaded the wrong pairs");
+    }
+}
+
+// Benchmark of bulk-loading sorted pairs into a new database, comparing
+// the default path with an INTEGER_KEY database (whose keys LMDB compares
+// as integers rather than byte strings) and with APPEND writes (which add
+// each pair to the end of the database rather than searching for its place),
+// as one might use to load time-series data.
+fn bench_bulk_load(c: &mut Criterion) {
+    c.bench(
+        "lmdb_bulk_load",
+        ParameterizedBenchmark::new(
+            "default",
+            |b, t| bench_load(b, t, DatabaseFlags::empty(), WriteFlags::empty()),
+            PARAMS.iter(),
+        )
+        .with_function("append", |b, t| bench_load(b, t, DatabaseFlags::empty(), WriteFlags::APPEND))
+        .with_function("integer_key", |b, t| bench_load(b, t, DatabaseFlags::INTEGER_KEY, WriteFlags::empty()))
+        .with_function("integer_key_append", |b, t| bench_load(b, t, DatabaseFlags::INTEGER_KEY, WriteFlags::APPEND))
+        .throughput(|t| t.throughput()),
     );
 }
 
-fn bench_put_rand_async(c: &mut Criterion) {
+// Benchmark of bulk-loading sorted pairs into a new DUP_SORT database,
+// comparing the default path with APPEND_DUP writes, which add each value
+// to the end of its key's values.  (APPEND would fail for every value
+// of a key after its first, since the key wouldn't be greater than the last.)
+fn bench_bulk_load_dup(c: &mut Criterion) {
+    c.bench(
+        "lmdb_bulk_load_dup",
+        ParameterizedBenchmark::new(
+            "default",
+            |b, t| bench_load(b, t, DatabaseFlags::DUP_SORT, WriteFlags::empty()),
+            DUP_PARAMS.iter(),
+        )
+        .with_function("append_dup", |b, t| bench_load(b, t, DatabaseFlags::DUP_SORT, WriteFlags::APPEND_DUP))
+        .throughput(|t| t.throughput()),
+    );
+}
+
+// Fill a value for the given key with a cheap pattern, standing in for
+// an application serializing a value into a buffer.
+fn fill_value(key: u32, value: &mut [u8]) {
+    for (i, byte) in value.iter_mut().enumerate() {
+        *byte = (key as usize + i) as u8;
+    }
+}
+
+// Overwrite the pairs, either filling each value in a Vec and putting it,
+// which copies the Vec into the map, or reserving space for each value
+// in the map and filling it in place.  We populate the database beforehand
+// so that every iteration overwrites values of the same size.
+fn bench_write_large(b: &mut Bencher, t: &Param, reserve: bool) {
+    let Param {
+        num_pairs,
+        size_values,
+        flags,
+    } = t;
+    let dir = TempDir::new("test").unwrap();
+    let env = get_env(*flags).open(dir.path()).unwrap();
+    let db = env.open_db(None).unwrap();
+    let write = || {
+        map_size::write(&env, |txn| {
+            for n in 0..*num_pairs {
+                if reserve {
+                    fill_value(n, txn.reserve(db, &get_key(n), *size_values, WriteFlags::empty())?);
+                } else {
+                    let mut value = vec![0; *size_values];
+                    fill_value(n, &mut value);
+                    txn.put(db, &get_key(n), &value, WriteFlags::empty())?;
+                }
+            }
+            Ok(())
+        })
+    };
+
+    write();
+    b.iter(&write);
+
+    let mut checksums = Checksums::new();
+    for n in 0..*num_pairs {
+        let mut value = vec![0; *size_values];
+        fill_value(n, &mut value);
+        checksums.insert(n, &value);
+    }
+    verify_db(&env, db, &checksums);
+}
+
+// Benchmark of writing large values with put, which copies them from
+// the caller's buffer into the map, compared to reserve, which returns
+// a buffer in the map for the caller to write them into directly.
+fn bench_reserve(c: &mut Criterion) {
+    c.bench(
+        "lmdb_reserve",
+        ParameterizedBenchmark::new("put", |b, t| bench_write_large(b, t, false), RESERVE_PARAMS.iter())
+            .with_function("reserve", |b, t| bench_write_large(b, t, true))
+            .throughput(|t| t.throughput()),
+    );
+}
+
+// Call `f` while another thread rewrites each pair with its current value,
+// one pair per transaction, so a benchmark can measure reads under concurrent
+// modification.  Rewriting the same values keeps the datastore's contents
+// verifiable, and pausing between writes keeps a transaction that a benchmark
+// holds from making the datastore grow too quickly.  We can't grow the map
+// while the benchmark's thread may hold a transaction open, so if the writes
+// fill the map anyway, the writer stops.
+fn with_writer<T, F: FnOnce() -> T>(env: &Environment, db: Database, num_pairs: u32, f: F) -> T {
+    map_size::set(env, CONCURRENT_MAP_SIZE).unwrap();
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            for key in (0..num_pairs).cycle() {
+                let result = map_size::try_write(env, |txn| {
+                    let value = txn.get(db, &get_key(key))?.to_vec();
+                    txn.put(db, &get_key(key), &value, WriteFlags::empty())
+                });
+                match result {
+                    Ok(()) => {},
+                    Err(Error::MapFull) => {
+                        eprintln!("the map is full, so the concurrent writer stopped");
+                        break;
+                    },
+                    Err(err) => panic!("{}", err),
+                }
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+                thread::sleep(WRITE_INTERVAL);
+            }
+        });
+        let result = f();
+        done.store(true, Ordering::Relaxed);
+        result
+    })
+}
+
+// Benchmark of beginning (and ending) a read transaction, which is LMDB's
+// equivalent of a LevelDB snapshot, while another thread writes
+// to the datastore.
+fn bench_begin_ro_txn(c: &mut Criterion) {
     c.bench_function_over_inputs(
-        "lmdb_put_rand_async",
+        "lmdb_begin_ro_txn",
         |b, ref t| {
             let Param {
                 num_pairs,
                 size_values,
+                flags,
             } = t;
-            let dir = TempDir::new("test").unwrap();
-            // LMDB writes are sync by default.  Set the MAP_ASYNC and WRITE_MAP
-            // environment flags to make them async (along with using a writeable
-            // memory map).
-            let env = get_env()
-                .set_flags(EnvironmentFlags::MAP_ASYNC | EnvironmentFlags::WRITE_MAP)
-                .open(dir.path())
-                .unwrap();
+            let (_dir, env, checksums) = setup_bench_db(*num_pairs, *size_values, *flags);
             let db = env.open_db(None).unwrap();
-            let mut pairs: Vec<([u8; 4], Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
-            thread_rng().shuffle(&mut pairs[..]);
 
-            b.iter(|| lmdb_put(&env, db, &pairs))
+            with_writer(&env, db, *num_pairs, || b.iter(|| env.begin_ro_txn().unwrap()));
+            verify_db(&env, db, &checksums);
         },
         PARAMS.iter(),
     );
 }
 
-fn bench_get_seq(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "lmdb_get_seq",
-        |b, ref t| {
-            let Param {
-                num_pairs,
-                size_values,
-            } = t;
-            let (_dir, env) = setup_bench_db(*num_pairs, *size_values);
-            let db = env.open_db(None).unwrap();
-            let keys: Vec<[u8; 4]> = (0..*num_pairs).map(|n| get_key(n)).collect();
+// Get the pairs in random order while another thread writes to the datastore,
+// either in a new read transaction per iteration, which sees the latest
+// values, or in a single long read transaction begun before the benchmark,
+// which sees the values as they were when it began.
+fn bench_get_concurrent(b: &mut Bencher, t: &Param, long_txn: bool) {
+    let Param {
+        num_pairs,
+        size_values,
+        flags,
+    } = t;
+    let (_dir, env, checksums) = setup_bench_db(*num_pairs, *size_values, *flags);
+    let db = env.open_db(None).unwrap();
+    let mut keys: Vec<[u8; 4]> = (0..*num_pairs).map(get_key).collect();
+    thread_rng().shuffle(&mut keys[..]);
 
+    with_writer(&env, db, *num_pairs, || {
+        if long_txn {
+            let txn = env.begin_ro_txn().unwrap();
             b.iter(|| {
-                let txn = env.begin_ro_txn().unwrap();
                 let mut i = 0usize;
                 for key in &keys {
                     i += txn.get(db, key).unwrap().len();
                 }
                 i
             })
-        },
-        PARAMS.iter(),
-    );
-}
-
-fn bench_get_rand(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "lmdb_get_rand",
-        |b, ref t| {
-            let Param {
-                num_pairs,
-                size_values,
-            } = t;
-            let (_dir, env) = setup_bench_db(*num_pairs, *size_values);
-            let db = env.open_db(None).unwrap();
-            let mut keys: Vec<[u8; 4]> = (0..*num_pairs).map(|n| get_key(n)).collect();
-            thread_rng().shuffle(&mut keys[..]);
-
+        } else {
             b.iter(|| {
                 let txn = env.begin_ro_txn().unwrap();
                 let mut i = 0usize;
@@ -300,37 +917,110 @@ fn bench_get_rand(c: &mut Criterion) {
                 }
                 i
             })
-        },
-        PARAMS.iter(),
+        }
+    });
+    verify_db(&env, db, &checksums);
+}
+
+fn bench_get_long_txn(c: &mut Criterion) {
+    c.bench(
+        "lmdb_get_long_txn",
+        ParameterizedBenchmark::new("short_txn", |b, t| bench_get_concurrent(b, t, false), PARAMS.iter())
+            .with_function("long_txn", |b, t| bench_get_concurrent(b, t, true))
+            .throughput(|t| t.throughput()),
     );
 }
 
-/// Benchmark of iterator sequential read performance.
-fn bench_get_seq_iter(c: &mut Criterion) {
-    c.bench_function_over_inputs(
-        "lmdb_get_seq_iter",
-        |b, ref t| {
-            let Param {
-                num_pairs,
-                size_values,
-            } = t;
-            let (_dir, env) = setup_bench_db(*num_pairs, *size_values);
-            let db = env.open_db(None).unwrap();
+// How the rollback benchmarks end the transaction in which they put pairs.
+#[derive(Clone, Copy)]
+enum Outcome {
+    Commit,
+    Abort,
+    NestedCommit,
+    NestedAbort,
+}
 
-            b.iter(|| {
-                let txn = env.begin_ro_txn().unwrap();
-                let mut cursor = txn.open_ro_cursor(db).unwrap();
-                let mut i = 0usize;
-                let mut count = 0u32;
-                for (key, data) in cursor.iter() {
-                    i += mem::size_of_val(&key) + data.len();
-                    count += 1;
+fn txn_put(txn: &mut RwTransaction, db: Database, pairs: &[([u8; 4], Vec<u8>)]) {
+    for (key, value) in pairs {
+        txn.put(db, key, value, WriteFlags::empty()).unwrap();
+    }
+}
+
+// Put the pairs in a transaction (or in a transaction nested in one) and then
+// commit or abort it.  We don't grow the map if it fills up, since the map
+// is large enough for every set of pairs, and a transaction that we abort
+// doesn't change the datastore anyway.
+fn bench_outcome(b: &mut Bencher, t: &Param, outcome: Outcome) {
+    let Param {
+        num_pairs,
+        size_values,
+        flags,
+    } = t;
+    let dir = TempDir::new("test").unwrap();
+    let env = get_env(*flags).open(dir.path()).unwrap();
+    let db = env.open_db(None).unwrap();
+    let pairs: Vec<([u8; 4], Vec<u8>)> = (0..*num_pairs).map(|n| get_pair(n, *size_values)).collect();
+
+    b.iter(|| {
+        let mut txn = env.begin_rw_txn().unwrap();
+        match outcome {
+            Outcome::Commit => {
+                txn_put(&mut txn, db, &pairs);
+                txn.commit().unwrap();
+            },
+            Outcome::Abort => {
+                txn_put(&mut txn, db, &pairs);
+                txn.abort();
+            },
+            Outcome::NestedCommit => {
+                {
+                    let mut nested = txn.begin_nested_txn().unwrap();
+                    txn_put(&mut nested, db, &pairs);
+                    nested.commit().unwrap();
                 }
-                assert_eq!(count, *num_pairs);
-                i
-            })
-        },
-        PARAMS.iter(),
+                txn.commit().unwrap();
+            },
+            Outcome::NestedAbort => {
+                {
+                    let mut nested = txn.begin_nested_txn().unwrap();
+                    txn_put(&mut nested, db, &pairs);
+                    nested.abort();
+                }
+                txn.commit().unwrap();
+            },
+        }
+    });
+
+    // Aborting the transactions should have left the datastore empty.
+    match outcome {
+        Outcome::Commit | Outcome::NestedCommit => verify_db(&env, db, &get_checksums(&pairs)),
+        Outcome::Abort | Outcome::NestedAbort => verify_db(&env, db, &Checksums::new()),
+    }
+}
+
+// Benchmark of rolling back speculative writes by aborting the transaction
+// that made them, compared to committing it.
+fn bench_rollback(c: &mut Criterion) {
+    c.bench(
+        "lmdb_rollback",
+        ParameterizedBenchmark::new("commit", |b, t| bench_outcome(b, t, Outcome::Commit), PARAMS.iter())
+            .with_function("abort", |b, t| bench_outcome(b, t, Outcome::Abort))
+            .throughput(|t| t.throughput()),
+    );
+}
+
+// Benchmark of rolling back speculative writes by aborting a nested
+// transaction and committing its parent, compared to committing both.
+fn bench_nested_txn(c: &mut Criterion) {
+    c.bench(
+        "lmdb_nested_txn",
+        ParameterizedBenchmark::new(
+            "nested_commit",
+            |b, t| bench_outcome(b, t, Outcome::NestedCommit),
+            NESTED_PARAMS.iter(),
+        )
+        .with_function("nested_abort", |b, t| bench_outcome(b, t, Outcome::NestedAbort))
+        .throughput(|t| t.throughput()),
     );
 }
 
@@ -345,8 +1035,9 @@ fn bench_db_size(c: &mut Criterion) {
             let Param {
                 num_pairs,
                 size_values,
+                flags,
             } = t;
-            let (dir, _env) = setup_bench_db(*num_pairs, *size_values);
+            let (dir, _env, _) = setup_bench_db(*num_pairs, *size_values, *flags);
             let mut total_size = 0;
 
             for entry in WalkDir::new(dir.path()) {
@@ -376,6 +1067,18 @@ criterion_group!(
     bench_get_seq,
     bench_get_rand,
     bench_get_seq_iter,
+    bench_get_seq_cold,
+    bench_get_rand_cold,
+    bench_get_seq_iter_cold,
+    bench_map_resize,
+    bench_put_map_full,
+    bench_bulk_load,
+    bench_bulk_load_dup,
+    bench_reserve,
+    bench_begin_ro_txn,
+    bench_get_long_txn,
+    bench_rollback,
+    bench_nested_txn,
     bench_db_size,
 );
 criterion_main!(benches);
@@ -0,0 +1,283 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Tests the crash consistency of the storage engines by killing a process
+// that is writing to a datastore with SIGKILL at a random point, reopening
+// the datastore, and verifying its contents.
+//
+// The child process writes numbered batches of pairs, each in a single
+// transaction (LMDB) or write (LevelDB), and acknowledges each batch
+// by printing its number to stdout once the write returns.  After killing
+// the child, the parent verifies that:
+//
+//   * every acknowledged batch is present, with the values it was written with;
+//   * the batch after the last acknowledged one (which the child may have
+//     committed without acknowledging it) is either entirely present or
+//     entirely absent, i.e. no batch was torn;
+//   * no other pairs are present.
+//
+// It runs a number of trials for each engine in both its sync and async modes.
+// Note that SIGKILL simulates a crash of the process, not of the system,
+// so writes the kernel has accepted survive it even in the async modes.
+//
+// Usage:
+//
+//   crash [--engine <lmdb|leveldb>] [--trials N] [--max-delay-ms N]
+//
+// The program can also leave a datastore in the state a crash would,
+// for the benches that measure how long it takes to reopen one:
+//
+//   crash --populate <lmdb|leveldb> DIR NUM_PAIRS
+//
+// populates the datastore in DIR with the given number of pairs and then
+// kills itself without closing the datastore.
+
+extern crate kvbench;
+extern crate libc;
+extern crate rand;
+extern crate tempdir;
+
+use kvbench::engine::{
+    Engine,
+    Leveldb,
+    Lmdb,
+};
+
+use rand::{
+    thread_rng,
+    Rng,
+};
+
+use std::env;
+use std::io::{
+    self,
+    BufRead,
+    BufReader,
+    Write,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::{
+    self,
+    Command,
+    Stdio,
+};
+use std::thread;
+use std::time::Duration;
+
+use tempdir::TempDir;
+
+const ENGINES: [&str; 2] = [Lmdb::NAME, Leveldb::NAME];
+
+// The number of pairs in each batch, and the size of their values.
+const BATCH_SIZE: u32 = 10;
+const VALUE_SIZE: usize = 100;
+
+// The number of pairs in each batch that --populate writes, which is larger
+// so that populating large datastores doesn't take forever.
+const POPULATE_BATCH_SIZE: u32 = 10_000;
+
+fn usage() -> ! {
+    eprintln!("usage: crash [--engine <lmdb|leveldb>] [--trials N] [--max-delay-ms N]");
+    process::exit(2);
+}
+
+// The value of the given key, which is written in the given batch.
+// Values are a function of their keys and batches, so the parent can
+// verify them without knowing anything but the number of the batch.
+fn get_value(batch: u32, key: u32) -> Vec<u8> {
+    let mut value = Vec::with_capacity(VALUE_SIZE);
+    value.extend_from_slice(&[(batch >> 24) as u8, (batch >> 16) as u8, (batch >> 8) as u8, batch as u8]);
+    value.extend((0..VALUE_SIZE - 4).map(|i| (key as usize + i) as u8));
+    value
+}
+
+fn get_batch(batch: u32) -> Vec<(u32, Vec<u8>)> {
+    (batch * BATCH_SIZE..(batch + 1) * BATCH_SIZE).map(|key| (key, get_value(batch, key))).collect()
+}
+
+// The entry point of the child process, which writes batches
+// until it is killed.
+fn child<E: Engine>(dir: &Path, sync: bool) {
+    let engine = E::open(dir, sync);
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    for batch in 0.. {
+        engine.write(&get_batch(batch));
+        writeln!(stdout, "{}", batch).unwrap();
+        stdout.flush().unwrap();
+    }
+}
+
+// The entry point of the process that --populate runs.
+fn populate<E: Engine>(dir: &Path, num_pairs: u32) {
+    let engine = E::open(dir, false);
+    let mut start = 0;
+    while start < num_pairs {
+        let end = num_pairs.min(start + POPULATE_BATCH_SIZE);
+        let pairs: Vec<(u32, Vec<u8>)> = (start..end).map(|key| (key, get_value(0, key))).collect();
+        engine.write(&pairs);
+        start = end;
+    }
+
+    unsafe {
+        libc::kill(libc::getpid(), libc::SIGKILL);
+    }
+}
+
+// Run a child process that writes to the datastore in `dir`, kill it
+// after the given delay, and return the number of the last batch
+// it acknowledged, if any.
+fn kill_child(engine: &str, dir: &Path, sync: bool, delay: Duration) -> Option<u32> {
+    let mut child = Command::new(env::current_exe().unwrap())
+        .arg("--child")
+        .arg(engine)
+        .arg(dir)
+        .arg(sync.to_string())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // Read acknowledgements as they arrive, so the child never blocks
+    // on a full pipe.
+    let stdout = child.stdout.take().unwrap();
+    let reader = thread::spawn(move || {
+        BufReader::new(stdout).lines().map(|line| line.unwrap().parse::<u32>().unwrap()).last()
+    });
+
+    thread::sleep(delay);
+
+    // Child::kill sends SIGKILL.
+    child.kill().unwrap();
+    child.wait().unwrap();
+    reader.join().unwrap()
+}
+
+// Verify the contents of the datastore, returning a description
+// of the first inconsistency we find.
+fn verify<E: Engine>(dir: &Path, last_acked: Option<u32>) -> Result<u32, String> {
+    let engine = E::open(dir, true);
+    let num_acked = last_acked.map_or(0, |batch| batch + 1);
+
+    for batch in 0..num_acked {
+        for (key, value) in get_batch(batch) {
+            match engine.get(key) {
+                Some(ref actual) if *actual == value => {},
+                Some(_) => return Err(format!("acknowledged batch {} has a wrong value for key {}", batch, key)),
+                None => return Err(format!("acknowledged batch {} is missing key {}", batch, key)),
+            }
+        }
+    }
+
+    // The child may have committed one more batch before we killed it.
+    let next = get_batch(num_acked);
+    let present = next.iter().filter(|&&(key, ref value)| engine.get(key).as_ref() == Some(value)).count();
+    let num_batches = match present as u32 {
+        0 => num_acked,
+        BATCH_SIZE => num_acked + 1,
+        _ => return Err(format!("unacknowledged batch {} is torn: {} of {} pairs", num_acked, present, BATCH_SIZE)),
+    };
+
+    let count = engine.scan();
+    if count != num_batches * BATCH_SIZE {
+        return Err(format!("expected {} pairs from {} batches, found {}", num_batches * BATCH_SIZE, num_batches, count));
+    }
+
+    Ok(num_batches)
+}
+
+fn run_trial<E: Engine>(sync: bool, delay: Duration) -> Result<u32, String> {
+    let dir = TempDir::new("crash").unwrap();
+    let last_acked = kill_child(E::NAME, dir.path(), sync, delay);
+    verify::<E>(dir.path(), last_acked)
+}
+
+fn parse_value<T: std::str::FromStr, I: Iterator<Item = String>>(args: &mut I) -> T {
+    args.next().and_then(|arg| arg.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    if env::args().nth(1).is_some_and(|arg| arg == "--child") {
+        args.next();
+        let engine: String = parse_value(&mut args);
+        let dir: PathBuf = parse_value(&mut args);
+        let sync = parse_value(&mut args);
+        match engine.as_str() {
+            Lmdb::NAME => child::<Lmdb>(&dir, sync),
+            Leveldb::NAME => child::<Leveldb>(&dir, sync),
+            _ => usage(),
+        }
+        return;
+    }
+
+    if env::args().nth(1).is_some_and(|arg| arg == "--populate") {
+        args.next();
+        let engine: String = parse_value(&mut args);
+        let dir: PathBuf = parse_value(&mut args);
+        let num_pairs = parse_value(&mut args);
+        match engine.as_str() {
+            Lmdb::NAME => populate::<Lmdb>(&dir, num_pairs),
+            Leveldb::NAME => populate::<Leveldb>(&dir, num_pairs),
+            _ => usage(),
+        }
+        return;
+    }
+
+    let mut engines: Vec<String> = ENGINES.iter().map(|e| e.to_string()).collect();
+    let mut trials = 20;
+    let mut max_delay_ms = 200;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--engine" => engines = vec![parse_value(&mut args)],
+            "--trials" => trials = parse_value(&mut args),
+            "--max-delay-ms" => max_delay_ms = parse_value(&mut args),
+            _ => usage(),
+        }
+    }
+    if !engines.iter().all(|e| ENGINES.contains(&e.as_str())) || max_delay_ms == 0 {
+        usage();
+    }
+
+    let mut failures = 0;
+    for engine in &engines {
+        for &sync in &[true, false] {
+            for trial in 0..trials {
+                let delay = Duration::from_millis(thread_rng().gen_range(1, max_delay_ms + 1));
+                let result = match engine.as_str() {
+                    Lmdb::NAME => run_trial::<Lmdb>(sync, delay),
+                    _ => run_trial::<Leveldb>(sync, delay),
+                };
+                let mode = if sync { "sync" } else { "async" };
+                match result {
+                    Ok(num_batches) => {
+                        println!("{} {} trial {}: ok, {} batches after {:?}", engine, mode, trial, num_batches, delay)
+                    },
+                    Err(err) => {
+                        println!("{} {} trial {}: FAILED after {:?}: {}", engine, mode, trial, delay, err);
+                        failures += 1;
+                    },
+                }
+            }
+        }
+    }
+
+    if failures > 0 {
+        eprintln!("{} trials failed", failures);
+        process::exit(1);
+    }
+}
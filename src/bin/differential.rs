@@ -0,0 +1,314 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Tests the storage engines (and the wrappers around them) against each other
+// by applying random sequences of operations to each engine and to an in-memory
+// BTreeMap, which serves as an oracle, and checking that every read returns
+// what the oracle does.
+//
+// Each sequence mixes puts, deletes, batches of both, gets, range scans,
+// and reopenings of the datastore over a small key space, so operations
+// often touch the same keys.  When an engine disagrees with the oracle
+// (or panics), the program minimizes the failing sequence by removing
+// operations for as long as the sequence still fails, then prints it.
+//
+// Usage:
+//
+//   differential [--engine <lmdb|leveldb>] [--sequences N] [--ops N] [--seed N]
+//
+// Sequence i is generated from seed + i, so a failure can be reproduced
+// by passing its seed with --sequences 1.
+
+extern crate kvbench;
+extern crate rand;
+extern crate tempdir;
+
+use kvbench::engine::{
+    Change,
+    Engine,
+    Leveldb,
+    Lmdb,
+};
+
+use rand::prng::XorShiftRng;
+use rand::{
+    random,
+    Rng,
+    SeedableRng,
+};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Debug;
+use std::panic::{
+    self,
+    AssertUnwindSafe,
+};
+use std::process;
+
+use tempdir::TempDir;
+
+const ENGINES: [&str; 2] = [Lmdb::NAME, Leveldb::NAME];
+
+// The number of distinct keys, which is small so that operations
+// frequently overwrite, delete, and scan over the same keys.
+const KEY_SPACE: u32 = 256;
+
+// The maximum size of a value (which may be empty) and of a batch.
+const MAX_VALUE_SIZE: usize = 32;
+const MAX_BATCH_SIZE: usize = 16;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Put(u32, Vec<u8>),
+    Delete(u32),
+    Batch(Vec<Change>),
+    Get(u32),
+    Range(u32, u32),
+    Reopen,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: differential [--engine <lmdb|leveldb>] [--sequences N] [--ops N] [--seed N]");
+    process::exit(2);
+}
+
+fn gen_value<R: Rng>(rng: &mut R) -> Vec<u8> {
+    let size = rng.gen_range(0, MAX_VALUE_SIZE + 1);
+    (0..size).map(|_| rng.gen()).collect()
+}
+
+fn gen_change<R: Rng>(rng: &mut R) -> Change {
+    let key = rng.gen_range(0, KEY_SPACE);
+    if rng.gen_range(0, 3) == 0 {
+        Change::Delete(key)
+    } else {
+        Change::Put(key, gen_value(rng))
+    }
+}
+
+fn gen_op<R: Rng>(rng: &mut R) -> Op {
+    match rng.gen_range(0, 100) {
+        0..=29 => Op::Put(rng.gen_range(0, KEY_SPACE), gen_value(rng)),
+        30..=44 => Op::Delete(rng.gen_range(0, KEY_SPACE)),
+        45..=59 => {
+            let size = rng.gen_range(1, MAX_BATCH_SIZE + 1);
+            Op::Batch((0..size).map(|_| gen_change(rng)).collect())
+        },
+        60..=74 => Op::Get(rng.gen_range(0, KEY_SPACE)),
+        // Ranges may start after the last key and end past the key space.
+        75..=97 => {
+            let start = rng.gen_range(0, KEY_SPACE + 16);
+            Op::Range(start, start + rng.gen_range(0, KEY_SPACE / 4))
+        },
+        _ => Op::Reopen,
+    }
+}
+
+fn gen_ops(seed: u64, num_ops: usize) -> Vec<Op> {
+    let mut bytes = [0u8; 16];
+    for (i, byte) in bytes.iter_mut().take(8).enumerate() {
+        *byte = (seed >> (i * 8)) as u8;
+    }
+    let mut rng = XorShiftRng::from_seed(bytes);
+    (0..num_ops).map(|_| gen_op(&mut rng)).collect()
+}
+
+fn apply_to_oracle(oracle: &mut BTreeMap<u32, Vec<u8>>, changes: &[Change]) {
+    for change in changes {
+        match *change {
+            Change::Put(key, ref value) => {
+                oracle.insert(key, value.clone());
+            },
+            Change::Delete(key) => {
+                oracle.remove(&key);
+            },
+        }
+    }
+}
+
+fn check<T: Debug + PartialEq>(name: &str, index: usize, op: &Op, actual: T, expected: T) -> Result<(), String> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!("op {} ({:?}): {} returned {:?}, expected {:?}", index, op, name, actual, expected))
+    }
+}
+
+// Apply the operations to a fresh datastore and the oracle, returning
+// a description of the first read on which they disagree.  After the last
+// operation, we also compare the entire contents of the datastore.
+fn run<E: Engine>(ops: &[Op]) -> Result<(), String> {
+    let dir = TempDir::new("differential").unwrap();
+    let mut engine = E::open(dir.path(), false);
+    let mut oracle = BTreeMap::new();
+
+    for (index, op) in ops.iter().enumerate() {
+        match *op {
+            Op::Put(key, ref value) => {
+                let changes = [Change::Put(key, value.clone())];
+                engine.apply(&changes);
+                apply_to_oracle(&mut oracle, &changes);
+            },
+            Op::Delete(key) => {
+                let changes = [Change::Delete(key)];
+                engine.apply(&changes);
+                apply_to_oracle(&mut oracle, &changes);
+            },
+            Op::Batch(ref changes) => {
+                engine.apply(changes);
+                apply_to_oracle(&mut oracle, changes);
+            },
+            Op::Get(key) => check(E::NAME, index, op, engine.get(key), oracle.get(&key).cloned())?,
+            Op::Range(start, end) => {
+                let expected: Vec<(u32, Vec<u8>)> = oracle.range(start..end).map(|(&k, v)| (k, v.clone())).collect();
+                check(E::NAME, index, op, engine.range(start, end), expected)?
+            },
+            Op::Reopen => {
+                drop(engine);
+                engine = E::open(dir.path(), false);
+            },
+        }
+    }
+
+    let expected: Vec<(u32, Vec<u8>)> = oracle.iter().map(|(&k, v)| (k, v.clone())).collect();
+    let end = Op::Range(0, u32::MAX);
+    check(E::NAME, ops.len(), &end, engine.range(0, u32::MAX), expected)?;
+    check(E::NAME, ops.len(), &end, engine.scan(), oracle.len() as u32)
+}
+
+// Run the operations, treating a panic (in the engine or its wrapper)
+// as a failure.
+fn run_catching<E: Engine>(ops: &[Op]) -> Result<(), String> {
+    match panic::catch_unwind(AssertUnwindSafe(|| run::<E>(ops))) {
+        Ok(result) => result,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_default();
+            Err(format!("{} panicked: {}", E::NAME, message))
+        },
+    }
+}
+
+// Shrink a failing sequence by removing ever-smaller chunks of operations,
+// then individual changes from batches, keeping each removal after which
+// the sequence still fails.  Returns the minimized sequence and its failure.
+fn minimize<E: Engine>(mut ops: Vec<Op>, mut failure: String) -> (Vec<Op>, String) {
+    let mut chunk = ops.len() / 2;
+    while chunk > 0 {
+        let mut start = 0;
+        while start < ops.len() {
+            let mut candidate = ops.clone();
+            candidate.drain(start..ops.len().min(start + chunk));
+            match run_catching::<E>(&candidate) {
+                Err(err) => {
+                    ops = candidate;
+                    failure = err;
+                },
+                Ok(()) => start += chunk,
+            }
+        }
+        chunk /= 2;
+    }
+
+    for index in 0..ops.len() {
+        let mut change = 0;
+        while let Op::Batch(ref changes) = ops[index].clone() {
+            if change >= changes.len() || changes.len() == 1 {
+                break;
+            }
+            let mut candidate = ops.clone();
+            let mut changes = changes.clone();
+            changes.remove(change);
+            candidate[index] = Op::Batch(changes);
+            match run_catching::<E>(&candidate) {
+                Err(err) => {
+                    ops = candidate;
+                    failure = err;
+                },
+                Ok(()) => change += 1,
+            }
+        }
+    }
+
+    (ops, failure)
+}
+
+fn test_sequence<E: Engine>(ops: &[Op]) -> bool {
+    match run_catching::<E>(ops) {
+        Ok(()) => true,
+        Err(failure) => {
+            let (ops, failure) = minimize::<E>(ops.to_vec(), failure);
+            println!("  minimized to {} ops:", ops.len());
+            for (index, op) in ops.iter().enumerate() {
+                println!("    {}: {:?}", index, op);
+            }
+            println!("  {}", failure);
+            false
+        },
+    }
+}
+
+fn parse_value<T: std::str::FromStr, I: Iterator<Item = String>>(args: &mut I) -> T {
+    args.next().and_then(|arg| arg.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut engines: Vec<String> = ENGINES.iter().map(|e| e.to_string()).collect();
+    let mut sequences = 100;
+    let mut num_ops = 1000;
+    let mut seed: u64 = random();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--engine" => engines = vec![parse_value(&mut args)],
+            "--sequences" => sequences = parse_value(&mut args),
+            "--ops" => num_ops = parse_value(&mut args),
+            "--seed" => seed = parse_value(&mut args),
+            _ => usage(),
+        }
+    }
+    if !engines.iter().all(|e| ENGINES.contains(&e.as_str())) {
+        usage();
+    }
+
+    // We report panics as failures, so don't also print them as they happen.
+    panic::set_hook(Box::new(|_| {}));
+
+    let mut failures = 0;
+    for sequence in 0..sequences {
+        let sequence_seed = seed.wrapping_add(sequence);
+        let ops = gen_ops(sequence_seed, num_ops);
+        for engine in &engines {
+            println!("{} sequence {} (seed {}):", engine, sequence, sequence_seed);
+            let ok = match engine.as_str() {
+                Lmdb::NAME => test_sequence::<Lmdb>(&ops),
+                _ => test_sequence::<Leveldb>(&ops),
+            };
+            if ok {
+                println!("  ok");
+            } else {
+                failures += 1;
+            }
+        }
+    }
+
+    if failures > 0 {
+        eprintln!("{} sequences failed", failures);
+        process::exit(1);
+    }
+}
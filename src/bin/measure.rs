@@ -0,0 +1,436 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Runs each workload in its own child process and reports measurements
+// of the process that Criterion can't take, like its memory footprint and
+// the I/O it performs, alongside the time the workload took.  The results
+// are printed to stdout as a CSV table with one row per engine, workload,
+// and parameter set.
+//
+// Running every workload in a fresh process means that its measurements
+// aren't polluted by the workloads that ran before it.  For the same reason,
+// the read workloads populate their datastore in a separate child process
+// before the one that reads it.
+//
+// Usage:
+//
+//   measure [--engine <lmdb|leveldb>] [--workload NAME] [--pairs N] [--size N]
+//           [--cold]
+//
+// The first four options limit the run to the given engine, workload,
+// number of pairs, or size of values, respectively.  The --cold option evicts
+// the datastore's files from the page cache before each read workload, so its
+// reads go to disk (otherwise they're satisfied by the page cache, and their
+// read amplification is zero).
+//
+// The I/O measurements compare the logical bytes (of keys and values)
+// a workload writes or reads to the bytes the process actually sent to
+// (or fetched from) the storage layer during the workload, as reported by
+// /proc/self/io, to derive its write (or read) amplification.  Writes that
+// an engine defers until after the workload, like LevelDB's background
+// compactions, are only counted if they happen before the workload ends.
+//
+// The sync measurements count the calls to fsync, fdatasync, and msync
+// the engine made during the workload, which verifies what each engine
+// actually does to make a commit durable in its "sync" and "async" modes.
+// The async mode is the one the benches use, which for LMDB is MAP_ASYNC
+// with a writeable memory map rather than the NO_SYNC of the other programs.
+// The *_each_* workloads commit every pair in its own transaction (LMDB)
+// or write (LevelDB), so their syncs_per_commit reflect the typical cost
+// of a commit rather than the cost of a single large one.
+//
+// The CPU measurements break the workload's time down into user and system
+// time, page faults, and context switches, as reported by getrusage(2).
+// The bg_* columns are the portion of the CPU time and page faults that are
+// attributable to threads other than the one running the workload, like
+// LevelDB's compaction thread.
+
+extern crate kvbench;
+extern crate libc;
+extern crate rand;
+extern crate tempdir;
+
+use kvbench::engine::{
+    Engine,
+    Leveldb,
+    Lmdb,
+};
+use kvbench::{
+    page_cache,
+    procfs,
+    rusage,
+};
+
+use rand::{
+    random,
+    thread_rng,
+    Rng,
+};
+
+use std::env;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::{
+    self,
+    Command,
+};
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use tempdir::TempDir;
+
+const ENGINES: [&str; 2] = [Lmdb::NAME, Leveldb::NAME];
+
+const WORKLOADS: [&str; 9] = [
+    "put_seq_sync",
+    "put_seq_async",
+    "put_seq_each_sync",
+    "put_seq_each_async",
+    "put_rand_sync",
+    "put_rand_async",
+    "get_seq",
+    "get_rand",
+    "get_seq_iter",
+];
+
+// These are larger than the benches' parameters, since a process's footprint
+// only becomes interesting once its datastore holds a meaningful amount of data.
+const PAIR_COUNTS: [u32; 3] = [1000, 10_000, 100_000];
+const VALUE_SIZES: [usize; 3] = [1, 100, 1000];
+
+// The measurements a child process reports, as (name, value) pairs.
+type Measurements = Vec<(&'static str, String)>;
+
+// Counts of the calls the process makes to the functions that flush data
+// to disk.  The storage engines are statically linked into this program,
+// so their calls to these functions resolve to the definitions here rather
+// than the ones in the C library, and these definitions then make the system
+// calls the C library would have made.
+mod syncs {
+    use libc::{
+        self,
+        c_int,
+        c_long,
+        c_void,
+        size_t,
+    };
+
+    use std::sync::atomic::{
+        AtomicUsize,
+        Ordering,
+    };
+
+    static FSYNC: AtomicUsize = AtomicUsize::new(0);
+    static FDATASYNC: AtomicUsize = AtomicUsize::new(0);
+    static MSYNC: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone, Copy)]
+    pub struct Counts {
+        pub fsync: usize,
+        pub fdatasync: usize,
+        pub msync: usize,
+    }
+
+    impl Counts {
+        pub fn since(&self, earlier: &Counts) -> Counts {
+            Counts {
+                fsync: self.fsync - earlier.fsync,
+                fdatasync: self.fdatasync - earlier.fdatasync,
+                msync: self.msync - earlier.msync,
+            }
+        }
+
+        pub fn total(&self) -> usize {
+            self.fsync + self.fdatasync + self.msync
+        }
+    }
+
+    pub fn counts() -> Counts {
+        Counts {
+            fsync: FSYNC.load(Ordering::SeqCst),
+            fdatasync: FDATASYNC.load(Ordering::SeqCst),
+            msync: MSYNC.load(Ordering::SeqCst),
+        }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn fsync(fd: c_int) -> c_int {
+        FSYNC.fetch_add(1, Ordering::SeqCst);
+        unsafe { libc::syscall(libc::SYS_fsync, fd as c_long) as c_int }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn fdatasync(fd: c_int) -> c_int {
+        FDATASYNC.fetch_add(1, Ordering::SeqCst);
+        unsafe { libc::syscall(libc::SYS_fdatasync, fd as c_long) as c_int }
+    }
+
+    #[no_mangle]
+    pub extern "C" fn msync(addr: *mut c_void, len: size_t, flags: c_int) -> c_int {
+        MSYNC.fetch_add(1, Ordering::SeqCst);
+        unsafe { libc::syscall(libc::SYS_msync, addr, len, flags as c_long) as c_int }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: measure [--engine <lmdb|leveldb>] [--workload NAME] [--pairs N] [--size N] [--cold]");
+    process::exit(2);
+}
+
+fn get_value(size_values: usize) -> Vec<u8> {
+    (0..size_values).map(|_| random()).collect()
+}
+
+fn get_pairs(num_pairs: u32, size_values: usize) -> Vec<(u32, Vec<u8>)> {
+    (0..num_pairs).map(|n| (n, get_value(size_values))).collect()
+}
+
+fn populate<E: Engine>(dir: &Path, num_pairs: u32, size_values: usize) {
+    let engine = E::open(dir, true);
+    engine.write(&get_pairs(num_pairs, size_values));
+}
+
+// Run the given workload, measuring the process once it completes,
+// while the datastore is still open.
+fn run_workload<E: Engine>(workload: &str, dir: &Path, num_pairs: u32, size_values: usize, cold: bool) -> Measurements {
+    if cold {
+        page_cache::evict(dir).unwrap();
+    }
+    // The async workloads use the benches' async mode, so their syncs
+    // describe the configuration that the benches measure.
+    let engine = if workload.ends_with("_async") {
+        E::open_bench_async(dir)
+    } else {
+        E::open(dir, true)
+    };
+
+    // Only the put workloads need pairs, and only the gets need keys, so the memory measurements don't include data a workload ignores.
+    let mut pairs = if workload.starts_with("put") {
+        get_pairs(num_pairs, size_values)
+    } else {
+        vec![]
+    };
+    let mut keys: Vec<u32> = if workload == "get_seq" || workload == "get_rand" {
+        (0..num_pairs).collect()
+    } else {
+        vec![]
+    };
+    if workload.starts_with("put_rand") {
+        thread_rng().shuffle(&mut pairs[..]);
+    } else if workload == "get_rand" {
+        thread_rng().shuffle(&mut keys[..]);
+    }
+
+    // Measure the peak resident set size of the workload alone, not of
+    // opening the datastore and generating its input, which we report
+    // as the resident set size at the start of the workload.
+    let start_memory = procfs::memory(dir).unwrap();
+    procfs::reset_peak_rss().unwrap();
+
+    let mut commits = 0;
+    let syncs_before = syncs::counts();
+    let io_before = procfs::io_counters().unwrap();
+    let process_before = rusage::process();
+    let thread_before = rusage::thread();
+    let start = Instant::now();
+    match workload {
+        "put_seq_sync" | "put_seq_async" | "put_rand_sync" | "put_rand_async" => {
+            engine.write(&pairs);
+            commits = 1;
+        },
+        "put_seq_each_sync" | "put_seq_each_async" => {
+            for pair in pairs.chunks(1) {
+                engine.write(pair);
+            }
+            commits = num_pairs;
+        },
+        "get_seq" | "get_rand" => assert_eq!(engine.read(&keys), num_pairs as usize * size_values),
+        "get_seq_iter" => assert_eq!(engine.scan(), num_pairs),
+        _ => usage(),
+    }
+    let elapsed = start.elapsed();
+    let thread = rusage::thread().since(&thread_before);
+    let process = rusage::process().since(&process_before);
+    let background = process.since(&thread);
+    let io = procfs::io_counters().unwrap().since(&io_before);
+    let syncs = syncs::counts().since(&syncs_before);
+
+    let memory = procfs::memory(dir).unwrap();
+
+    // Every workload writes or reads every pair exactly once.
+    let logical_bytes = u64::from(num_pairs) * (4 + size_values as u64);
+    let (read_amplification, write_amplification) = if workload.starts_with("put") {
+        (String::new(), ratio(io.physical_write_bytes(), logical_bytes))
+    } else {
+        (ratio(io.read_bytes, logical_bytes), String::new())
+    };
+
+    vec![
+        ("elapsed_us", micros(elapsed)),
+        ("start_rss_kb", start_memory.rss.to_string()),
+        ("peak_rss_kb", memory.peak_rss.to_string()),
+        ("rss_kb", memory.rss.to_string()),
+        ("mapped_rss_kb", memory.mapped_rss.to_string()),
+        ("logical_bytes", logical_bytes.to_string()),
+        ("rchar", io.rchar.to_string()),
+        ("wchar", io.wchar.to_string()),
+        ("syscr", io.syscr.to_string()),
+        ("syscw", io.syscw.to_string()),
+        ("read_bytes", io.read_bytes.to_string()),
+        ("write_bytes", io.physical_write_bytes().to_string()),
+        ("read_amplification", read_amplification),
+        ("write_amplification", write_amplification),
+        ("commits", commits.to_string()),
+        ("fsync", syncs.fsync.to_string()),
+        ("fdatasync", syncs.fdatasync.to_string()),
+        ("msync", syncs.msync.to_string()),
+        ("syncs_per_commit", if commits > 0 { ratio(syncs.total() as u64, u64::from(commits)) } else { String::new() }),
+        ("utime_us", micros(process.user_time)),
+        ("stime_us", micros(process.system_time)),
+        ("minflt", process.minor_faults.to_string()),
+        ("majflt", process.major_faults.to_string()),
+        ("nvcsw", process.voluntary_switches.to_string()),
+        ("nivcsw", process.involuntary_switches.to_string()),
+        ("bg_cpu_us", micros(background.cpu_time)),
+        ("bg_minflt", background.minor_faults.to_string()),
+        ("bg_majflt", background.major_faults.to_string()),
+    ]
+}
+
+fn micros(duration: Duration) -> String {
+    (duration.as_secs() * 1_000_000 + u64::from(duration.subsec_micros())).to_string()
+}
+
+fn ratio(numerator: u64, denominator: u64) -> String {
+    format!("{:.2}", numerator as f64 / denominator as f64)
+}
+
+// The entry point of a child process, which runs a single workload
+// and prints its measurements to stdout as space-separated name=value pairs.
+fn child<E: Engine>(workload: &str, dir: &Path, num_pairs: u32, size_values: usize, cold: bool) {
+    if workload == "populate" {
+        populate::<E>(dir, num_pairs, size_values);
+        return;
+    }
+
+    let measurements = run_workload::<E>(workload, dir, num_pairs, size_values, cold);
+    let fields: Vec<String> = measurements.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+    println!("{}", fields.join(" "));
+}
+
+fn spawn_child(
+    engine: &str,
+    workload: &str,
+    dir: &Path,
+    num_pairs: u32,
+    size_values: usize,
+    cold: bool,
+) -> Vec<(String, String)> {
+    let output = Command::new(env::current_exe().unwrap())
+        .arg("--child")
+        .arg(engine)
+        .arg(workload)
+        .arg(dir)
+        .arg(num_pairs.to_string())
+        .arg(size_values.to_string())
+        .arg(cold.to_string())
+        .output()
+        .unwrap();
+    if !output.status.success() {
+        panic!("{} {} failed: {}", engine, workload, String::from_utf8_lossy(&output.stderr));
+    }
+
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .split_whitespace()
+        .map(|field| {
+            let mut parts = field.splitn(2, '=');
+            let name = parts.next().unwrap().to_owned();
+            let value = parts.next().unwrap_or("").to_owned();
+            (name, value)
+        })
+        .collect()
+}
+
+fn parse_value<T: std::str::FromStr, I: Iterator<Item = String>>(args: &mut I) -> T {
+    args.next().and_then(|arg| arg.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+
+    if env::args().nth(1).is_some_and(|arg| arg == "--child") {
+        args.next();
+        let engine: String = parse_value(&mut args);
+        let workload: String = parse_value(&mut args);
+        let dir: PathBuf = parse_value(&mut args);
+        let num_pairs = parse_value(&mut args);
+        let size_values = parse_value(&mut args);
+        let cold = parse_value(&mut args);
+        match engine.as_str() {
+            Lmdb::NAME => child::<Lmdb>(&workload, &dir, num_pairs, size_values, cold),
+            Leveldb::NAME => child::<Leveldb>(&workload, &dir, num_pairs, size_values, cold),
+            _ => usage(),
+        }
+        return;
+    }
+
+    let mut engines: Vec<String> = ENGINES.iter().map(|e| e.to_string()).collect();
+    let mut workloads: Vec<String> = WORKLOADS.iter().map(|w| w.to_string()).collect();
+    let mut pair_counts: Vec<u32> = PAIR_COUNTS.to_vec();
+    let mut value_sizes: Vec<usize> = VALUE_SIZES.to_vec();
+    let mut cold = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--engine" => engines = vec![parse_value(&mut args)],
+            "--workload" => workloads = vec![parse_value(&mut args)],
+            "--pairs" => pair_counts = vec![parse_value(&mut args)],
+            "--size" => value_sizes = vec![parse_value(&mut args)],
+            "--cold" => cold = true,
+            _ => usage(),
+        }
+    }
+    if !engines.iter().all(|e| ENGINES.contains(&e.as_str()))
+        || !workloads.iter().all(|w| WORKLOADS.contains(&w.as_str()))
+    {
+        usage();
+    }
+
+    let mut printed_header = false;
+    for engine in &engines {
+        for workload in &workloads {
+            for &num_pairs in &pair_counts {
+                for &size_values in &value_sizes {
+                    let dir = TempDir::new("measure").unwrap();
+                    if workload.starts_with("get") {
+                        spawn_child(engine, "populate", dir.path(), num_pairs, size_values, false);
+                    }
+                    let measurements = spawn_child(engine, workload, dir.path(), num_pairs, size_values, cold);
+
+                    if !printed_header {
+                        let names: Vec<&str> = measurements.iter().map(|(name, _)| name.as_str()).collect();
+                        println!("engine,workload,num_pairs,size_values,{}", names.join(","));
+                        printed_header = true;
+                    }
+                    let values: Vec<&str> = measurements.iter().map(|(_, value)| value.as_str()).collect();
+                    println!("{},{},{},{},{}", engine, workload, num_pairs, size_values, values.join(","));
+                }
+            }
+        }
+    }
+}
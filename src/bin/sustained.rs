@@ -0,0 +1,312 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Writes to a storage engine continuously for a given duration (or until
+// a given amount of data has been written) and records the throughput
+// and commit latency of every one-second window to a CSV time series,
+// along with the space the datastore takes on disk at the end of the window.
+//
+// Criterion's samples are too short to trigger LevelDB's background
+// compaction or the growth of LMDB's freelist, so this mode exposes
+// behavior that the benches can't: write stalls, L0 slowdowns, and
+// the steady-state performance of each engine.
+//
+// Usage:
+//
+//   sustained <lmdb|leveldb> [--minutes N] [--gigabytes N] [--keys N]
+//             [--value-size N] [--batch-size N] [--sync] [--reader]
+//             [--dir PATH] [--output PATH]
+//
+// The run stops after --minutes or once --gigabytes of keys and values
+// have been written, whichever comes first (ten minutes by default).
+// Keys cycle through the range [0, --keys), so a long run overwrites
+// each key many times.  The time series goes to stdout unless --output
+// is specified.
+//
+// The --reader option holds a read transaction (LMDB) or snapshot (LevelDB)
+// open for the whole run, as a long-lived reader would.  LMDB can't reuse
+// the pages that such a reader can see, and LevelDB can't discard the values
+// it can see while compacting, so the datastore grows as the run overwrites
+// its keys, rather than leveling off once it has written each key.
+// LMDB can't grow its map while the reader is open, so it caps the map
+// at 4GiB more than the data it holds when the run starts, and the run
+// stops early (reporting that the datastore is full) once it fills the map.
+
+extern crate kvbench;
+extern crate rand;
+extern crate tempdir;
+
+use kvbench::engine::{
+    Engine,
+    Leveldb,
+    Lmdb,
+};
+
+use rand::random;
+
+use std::env;
+use std::fs::{
+    self,
+    File,
+};
+use std::io::{
+    self,
+    Write,
+};
+use std::os::unix::fs::MetadataExt;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process;
+use std::time::{
+    Duration,
+    Instant,
+};
+
+use tempdir::TempDir;
+
+// The number of distinct values we cycle through, so that generating random
+// values doesn't accrue to the write latency we measure.
+const VALUE_POOL_SIZE: usize = 1024;
+
+struct Config {
+    engine: String,
+    minutes: f64,
+    gigabytes: f64,
+    keys: u32,
+    value_size: usize,
+    batch_size: u32,
+    sync: bool,
+    reader: bool,
+    dir: Option<PathBuf>,
+    output: Option<PathBuf>,
+}
+
+// The measurements for one window of the time series.
+struct Window {
+    start: Duration,
+    ops: u64,
+    bytes: u64,
+    latencies: Vec<Duration>,
+}
+
+impl Window {
+    fn new(start: Duration) -> Window {
+        Window {
+            start,
+            ops: 0,
+            bytes: 0,
+            latencies: vec![],
+        }
+    }
+
+    fn write_row<W: Write>(&mut self, out: &mut W, end: Duration, disk_bytes: u64) -> io::Result<()> {
+        let secs = as_secs(end - self.start);
+        self.latencies.sort();
+        let mean = if self.latencies.is_empty() {
+            0.0
+        } else {
+            self.latencies.iter().map(|&l| as_micros(l)).sum::<f64>() / self.latencies.len() as f64
+        };
+        writeln!(
+            out,
+            "{:.3},{},{},{:.1},{:.1},{:.1},{:.1},{:.1},{:.1},{}",
+            as_secs(end),
+            self.ops,
+            self.bytes,
+            self.ops as f64 / secs,
+            self.bytes as f64 / secs,
+            mean,
+            percentile(&self.latencies, 0.5),
+            percentile(&self.latencies, 0.99),
+            percentile(&self.latencies, 1.0),
+            disk_bytes,
+        )
+    }
+}
+
+fn as_secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9
+}
+
+fn as_micros(d: Duration) -> f64 {
+    as_secs(d) * 1e6
+}
+
+// The given percentile of a sorted list of latencies, in microseconds.
+fn percentile(sorted: &[Duration], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    as_micros(sorted[index])
+}
+
+// The space the files in the given directory take on disk, which we count
+// in allocated blocks, so a sparse file only counts the blocks it has written.
+fn disk_usage(path: &Path) -> u64 {
+    fs::read_dir(path).unwrap().map(|entry| entry.unwrap().metadata().unwrap().blocks() * 512).sum()
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: sustained <lmdb|leveldb> [--minutes N] [--gigabytes N] [--keys N] \
+         [--value-size N] [--batch-size N] [--sync] [--reader] [--dir PATH] [--output PATH]"
+    );
+    process::exit(2);
+}
+
+fn parse_value<T: std::str::FromStr, I: Iterator<Item = String>>(args: &mut I) -> T {
+    args.next().and_then(|arg| arg.parse().ok()).unwrap_or_else(|| usage())
+}
+
+fn parse_args() -> Config {
+    let mut args = env::args().skip(1);
+    let engine = args.next().unwrap_or_else(|| usage());
+    let mut config = Config {
+        engine,
+        minutes: 0.0,
+        gigabytes: 0.0,
+        keys: 1_000_000,
+        value_size: 100,
+        batch_size: 1,
+        sync: false,
+        reader: false,
+        dir: None,
+        output: None,
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--minutes" => config.minutes = parse_value(&mut args),
+            "--gigabytes" => config.gigabytes = parse_value(&mut args),
+            "--keys" => config.keys = parse_value(&mut args),
+            "--value-size" => config.value_size = parse_value(&mut args),
+            "--batch-size" => config.batch_size = parse_value(&mut args),
+            "--sync" => config.sync = true,
+            "--reader" => config.reader = true,
+            "--dir" => config.dir = Some(parse_value(&mut args)),
+            "--output" => config.output = Some(parse_value(&mut args)),
+            _ => usage(),
+        }
+    }
+
+    if config.keys == 0 || config.batch_size == 0 {
+        usage();
+    }
+
+    // Run for ten minutes unless the user limits the run some other way.
+    if config.minutes == 0.0 && config.gigabytes == 0.0 {
+        config.minutes = 10.0;
+    }
+
+    config
+}
+
+fn run<E: Engine, W: Write>(config: &Config, out: &mut W) -> io::Result<()> {
+    // Keep the temporary directory alive until the run completes.
+    let temp_dir;
+    let path = match config.dir {
+        Some(ref dir) => dir.clone(),
+        None => {
+            temp_dir = TempDir::new("sustained").unwrap();
+            temp_dir.path().to_path_buf()
+        },
+    };
+    let engine = E::open(&path, config.sync);
+
+    if config.reader {
+        engine.with_reader(|| write_series(config, &engine, &path, out))
+    } else {
+        write_series(config, &engine, &path, out)
+    }
+}
+
+fn write_series<E: Engine, W: Write>(config: &Config, engine: &E, path: &Path, out: &mut W) -> io::Result<()> {
+    let values: Vec<Vec<u8>> =
+        (0..VALUE_POOL_SIZE).map(|_| (0..config.value_size).map(|_| random()).collect()).collect();
+    let max_duration = Duration::from_millis((config.minutes * 60.0 * 1000.0) as u64);
+    let max_bytes = (config.gigabytes * 1024.0 * 1024.0 * 1024.0) as u64;
+    let size_pair = 4 + config.value_size as u64;
+
+    writeln!(
+        out,
+        "elapsed_secs,ops,bytes,ops_per_sec,bytes_per_sec,\
+         latency_mean_us,latency_p50_us,latency_p99_us,latency_max_us,disk_bytes"
+    )?;
+
+    let start = Instant::now();
+    let mut window = Window::new(Duration::from_secs(0));
+    let mut total_bytes = 0u64;
+    let mut next_key = 0u32;
+    let mut next_value = 0usize;
+
+    loop {
+        // Once we've written every key, we start overwriting them, which is
+        // what provokes compaction in LevelDB and page reuse in LMDB.
+        let pairs: Vec<(u32, Vec<u8>)> = (0..config.batch_size)
+            .map(|_| {
+                let pair = (next_key, values[next_value].clone());
+                next_key = (next_key + 1) % config.keys;
+                next_value = (next_value + 1) % VALUE_POOL_SIZE;
+                pair
+            })
+            .collect();
+
+        let before = Instant::now();
+        let full = engine.try_write(&pairs).is_err();
+        let after = Instant::now();
+
+        if !full {
+            window.ops += u64::from(config.batch_size);
+            window.bytes += u64::from(config.batch_size) * size_pair;
+            window.latencies.push(after - before);
+            total_bytes += u64::from(config.batch_size) * size_pair;
+        }
+
+        let elapsed = after - start;
+        if full {
+            eprintln!("{}: the datastore is full, so the run stopped after {:.1}s", E::NAME, as_secs(elapsed));
+        }
+        let done = full
+            || (max_duration > Duration::from_secs(0) && elapsed >= max_duration)
+            || (max_bytes > 0 && total_bytes >= max_bytes);
+        if done || elapsed - window.start >= Duration::from_secs(1) {
+            window.write_row(out, elapsed, disk_usage(path))?;
+            window = Window::new(elapsed);
+        }
+        if done {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let config = parse_args();
+
+    let mut out: Box<dyn Write> = match config.output {
+        Some(ref path) => Box::new(File::create(path).unwrap()),
+        None => Box::new(io::stdout()),
+    };
+
+    let result = match config.engine.as_str() {
+        Lmdb::NAME => run::<Lmdb, _>(&config, &mut out),
+        Leveldb::NAME => run::<Leveldb, _>(&config, &mut out),
+        _ => usage(),
+    };
+    result.unwrap();
+}
@@ -0,0 +1,115 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use engine::{
+    Change,
+    Engine,
+    Full,
+};
+
+use leveldb::database::batch::{
+    Batch,
+    Writebatch,
+};
+
+use leveldb::database::snapshots::Snapshots;
+use leveldb::database::Database;
+use leveldb::iterator::{
+    Iterable,
+    LevelDBIterator,
+};
+use leveldb::kv::KV;
+
+use leveldb::options::{
+    Options,
+    ReadOptions,
+    WriteOptions,
+};
+
+use std::path::Path;
+
+pub struct Leveldb {
+    db: Database<i32>,
+    sync: bool,
+}
+
+impl Engine for Leveldb {
+    const NAME: &'static str = "leveldb";
+
+    fn open(path: &Path, sync: bool) -> Leveldb {
+        let mut options = Options::new();
+        options.create_if_missing = true;
+        let db = Database::open(path, options).unwrap();
+        Leveldb { db, sync }
+    }
+
+    fn write(&self, pairs: &[(u32, Vec<u8>)]) {
+        // LevelDB writes are async by default.  Set WriteOptions::sync
+        // to true to make them sync.
+        let mut write_opts = WriteOptions::new();
+        write_opts.sync = self.sync;
+        let batch = &mut Writebatch::new();
+        for (key, value) in pairs {
+            batch.put(*key as i32, value);
+        }
+        self.db.write(write_opts, batch).unwrap();
+    }
+
+    fn try_write(&self, pairs: &[(u32, Vec<u8>)]) -> Result<(), Full> {
+        self.write(pairs);
+        Ok(())
+    }
+
+    fn apply(&self, changes: &[Change]) {
+        let mut write_opts = WriteOptions::new();
+        write_opts.sync = self.sync;
+        let batch = &mut Writebatch::new();
+        for change in changes {
+            match *change {
+                Change::Put(key, ref value) => batch.put(key as i32, value),
+                Change::Delete(key) => batch.delete(key as i32),
+            }
+        }
+        self.db.write(write_opts, batch).unwrap();
+    }
+
+    fn get(&self, key: u32) -> Option<Vec<u8>> {
+        self.db.get(ReadOptions::new(), key as i32).unwrap()
+    }
+
+    fn read(&self, keys: &[u32]) -> usize {
+        keys.iter().map(|key| self.db.get(ReadOptions::new(), *key as i32).unwrap().unwrap().len()).sum()
+    }
+
+    fn scan(&self) -> u32 {
+        self.db.iter(ReadOptions::new()).count() as u32
+    }
+
+    fn range(&self, start: u32, end: u32) -> Vec<(u32, Vec<u8>)> {
+        // Iterator::to only affects where a reverse iteration starts,
+        // so we stop at the end of the range ourselves.
+        let start = start as i32;
+        self.db
+            .iter(ReadOptions::new())
+            .from(&start)
+            .map(|(key, value)| (key as u32, value))
+            .take_while(|&(key, _)| key < end)
+            .collect()
+    }
+
+    fn with_reader<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let _snapshot = self.db.snapshot();
+        f()
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use engine::{
+    Change,
+    Engine,
+    Full,
+};
+
+use lmdb::{
+    Cursor,
+    Database,
+    Environment,
+    EnvironmentFlags,
+    Error,
+    RwTransaction,
+    Transaction,
+    WriteFlags,
+};
+
+use lmdb_sys::MDB_SET_RANGE;
+
+use map_size;
+
+use std::path::Path;
+use std::sync::atomic::{
+    AtomicBool,
+    Ordering,
+};
+use std::sync::mpsc;
+use std::thread;
+
+// Long-running workloads write far more data than the benches, so we reserve
+// a map that is large enough for most of them, and grow it if a write fills
+// it anyway.  On a 64-bit system, the map only consumes address space until
+// pages are actually written.
+const GB: usize = 1024 * 1024 * 1024;
+const MAP_SIZE: usize = 64 * GB;
+
+// LMDB can't reuse the pages that a long-lived reader can see, so while
+// one is open, every commit copies the pages it changes to new ones,
+// and the datastore can grow by more than a gigabyte per second.  We can't
+// grow the map while the reader is open, so we cap the map at this many bytes
+// more than the data it holds when the reader opens.
+const READER_MAP_GROWTH: usize = 4 * GB;
+
+pub struct Lmdb {
+    env: Environment,
+    db: Database,
+    // Whether another thread holds a read transaction open (see with_reader),
+    // in which case we mustn't grow the map.
+    reader: AtomicBool,
+}
+
+// Encode a key in big-endian order, so keys sort numerically.
+fn get_key(n: u32) -> [u8; 4] {
+    let b1: u8 = ((n >> 24) & 0xff) as u8;
+    let b2: u8 = ((n >> 16) & 0xff) as u8;
+    let b3: u8 = ((n >> 8) & 0xff) as u8;
+    let b4: u8 = (n & 0xff) as u8;
+    [b1, b2, b3, b4]
+}
+
+fn from_key(key: &[u8]) -> u32 {
+    assert_eq!(key.len(), 4);
+    u32::from_be_bytes([key[0], key[1], key[2], key[3]])
+}
+
+impl Engine for Lmdb {
+    const NAME: &'static str = "lmdb";
+
+    fn open(path: &Path, sync: bool) -> Lmdb {
+        let mut builder = Environment::new();
+        builder.set_map_size(MAP_SIZE);
+        // LMDB writes are sync by default.  Set the NO_SYNC environment flag
+        // to make them async, which also skips syncing the meta page.
+        // Unlike the benches, we don't use a writeable memory map, since LMDB
+        // would then extend the data file to the full size of our large map,
+        // which fails on filesystems without sparse files (and MAP_ASYNC
+        // has no effect without one).
+        if !sync {
+            builder.set_flags(EnvironmentFlags::NO_SYNC);
+        }
+        Lmdb::from_env(builder.open(path).unwrap())
+    }
+
+    // The benches make writes async with the MAP_ASYNC and WRITE_MAP flags,
+    // which flush the map with msync(MS_ASYNC).  To keep the data file
+    // from growing to the size of our large map, we start with LMDB's default
+    // map size and let writes grow it.
+    fn open_bench_async(path: &Path) -> Lmdb {
+        let mut builder = Environment::new();
+        builder.set_flags(EnvironmentFlags::MAP_ASYNC | EnvironmentFlags::WRITE_MAP);
+        Lmdb::from_env(builder.open(path).unwrap())
+    }
+
+    fn write(&self, pairs: &[(u32, Vec<u8>)]) {
+        self.try_write(pairs).expect("map full while a reader is open");
+    }
+
+    fn try_write(&self, pairs: &[(u32, Vec<u8>)]) -> Result<(), Full> {
+        self.write_txn(|txn| {
+            for (key, value) in pairs {
+                txn.put(self.db, &get_key(*key), value, WriteFlags::empty())?;
+            }
+            Ok(())
+        })
+    }
+
+    fn apply(&self, changes: &[Change]) {
+        self.write_txn(|txn| {
+            for change in changes {
+                match *change {
+                    Change::Put(key, ref value) => txn.put(self.db, &get_key(key), value, WriteFlags::empty())?,
+                    Change::Delete(key) => match txn.del(self.db, &get_key(key), None) {
+                        Ok(()) | Err(Error::NotFound) => {},
+                        Err(err) => return Err(err),
+                    },
+                }
+            }
+            Ok(())
+        })
+        .expect("map full while a reader is open");
+    }
+
+    fn get(&self, key: u32) -> Option<Vec<u8>> {
+        let txn = self.env.begin_ro_txn().unwrap();
+        match txn.get(self.db, &get_key(key)) {
+            Ok(value) => Some(value.to_vec()),
+            Err(Error::NotFound) => None,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn read(&self, keys: &[u32]) -> usize {
+        let txn = self.env.begin_ro_txn().unwrap();
+        keys.iter().map(|key| txn.get(self.db, &get_key(*key)).unwrap().len()).sum()
+    }
+
+    fn scan(&self) -> u32 {
+        let txn = self.env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(self.db).unwrap();
+        cursor.iter().count() as u32
+    }
+
+    fn range(&self, start: u32, end: u32) -> Vec<(u32, Vec<u8>)> {
+        let txn = self.env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(self.db).unwrap();
+        // Cursor::iter_from panics if no key is greater than or equal to
+        // the one it starts from, so check for such a key first.
+        match cursor.get(Some(&get_key(start)), None, MDB_SET_RANGE) {
+            Ok(_) => {},
+            Err(Error::NotFound) => return vec![],
+            Err(err) => panic!("{}", err),
+        }
+        cursor
+            .iter_from(get_key(start))
+            .map(|(key, value)| (from_key(key), value.to_vec()))
+            .take_while(|&(key, _)| key < end)
+            .collect()
+    }
+
+    fn with_reader<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        map_size::set(&self.env, map_size::used(&self.env) + READER_MAP_GROWTH).unwrap();
+
+        let (opened_sender, opened_receiver) = mpsc::channel();
+        let (release_sender, release_receiver) = mpsc::channel::<()>();
+        let env = &self.env;
+        let result = thread::scope(|scope| {
+            scope.spawn(move || {
+                let _txn = env.begin_ro_txn().unwrap();
+                opened_sender.send(()).unwrap();
+                // This fails if `f` panics and drops the sender, which also
+                // releases the reader.
+                let _ = release_receiver.recv();
+            });
+            opened_receiver.recv().unwrap();
+
+            self.reader.store(true, Ordering::SeqCst);
+            let result = f();
+            self.reader.store(false, Ordering::SeqCst);
+            release_sender.send(()).unwrap();
+            result
+        });
+
+        map_size::set(&self.env, MAP_SIZE).unwrap();
+        result
+    }
+}
+
+impl Lmdb {
+    fn from_env(env: Environment) -> Lmdb {
+        let db = env.open_db(None).unwrap();
+        Lmdb {
+            env,
+            db,
+            reader: AtomicBool::new(false),
+        }
+    }
+
+    // Run `f` in a write transaction and commit it, growing the map if it
+    // fills up, unless a reader is open, in which case we return Err(Full).
+    fn write_txn<F>(&self, f: F) -> Result<(), Full>
+    where
+        F: FnMut(&mut RwTransaction) -> Result<(), Error>,
+    {
+        if !self.reader.load(Ordering::SeqCst) {
+            map_size::write(&self.env, f);
+            return Ok(());
+        }
+        match map_size::try_write(&self.env, f) {
+            Ok(()) => Ok(()),
+            Err(Error::MapFull) => Err(Full),
+            Err(err) => panic!("{}", err),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A minimal interface to the storage engines, so programs can run the same
+//! workload against either one.
+
+mod leveldb;
+mod lmdb;
+
+pub use self::leveldb::Leveldb;
+pub use self::lmdb::Lmdb;
+
+use std::path::Path;
+
+/// A change to a datastore.
+#[derive(Clone, Debug)]
+pub enum Change {
+    Put(u32, Vec<u8>),
+    Delete(u32),
+}
+
+/// The error a write returns when the datastore is full and can't grow,
+/// which only happens to LMDB while a reader is open (see `with_reader`).
+#[derive(Debug)]
+pub struct Full;
+
+/// A storage engine holding a single table of pairs whose keys are `u32`s.
+pub trait Engine: Sized {
+    /// The name of the engine, as it appears in benchmark names.
+    const NAME: &'static str;
+
+    /// Open the datastore in the given directory, creating it if missing.
+    /// If `sync` is true, then writes are durable once `write` returns;
+    /// otherwise the engine may defer flushing them to disk.
+    fn open(path: &Path, sync: bool) -> Self;
+
+    /// Open the datastore in the given directory in the async mode
+    /// that the benches use, which for LMDB differs from that of `open`.
+    fn open_bench_async(path: &Path) -> Self {
+        Self::open(path, false)
+    }
+
+    /// Write the given pairs in a single transaction (LMDB) or batch (LevelDB).
+    fn write(&self, pairs: &[(u32, Vec<u8>)]);
+
+    /// Write the given pairs like `write`, but return `Err(Full)` rather than
+    /// panicking if the datastore is full and can't grow.
+    fn try_write(&self, pairs: &[(u32, Vec<u8>)]) -> Result<(), Full>;
+
+    /// Apply the given changes in order, in a single transaction (LMDB)
+    /// or batch (LevelDB).  Deleting a key that doesn't exist does nothing.
+    fn apply(&self, changes: &[Change]);
+
+    /// Get the value of the given key, if it exists.
+    fn get(&self, key: u32) -> Option<Vec<u8>>;
+
+    /// Read the values of the given keys, which must all exist, in a single
+    /// transaction (LMDB), returning the total size of the values.
+    fn read(&self, keys: &[u32]) -> usize;
+
+    /// Iterate over every pair in key order, returning the number of pairs.
+    fn scan(&self) -> u32;
+
+    /// Get the pairs whose keys are in the range [start, end), in key order.
+    fn range(&self, start: u32, end: u32) -> Vec<(u32, Vec<u8>)>;
+
+    /// Call `f` while holding a read transaction (LMDB) or snapshot (LevelDB)
+    /// open, which keeps the engine from reclaiming the space of the pairs
+    /// that the reader can see, even once they've been overwritten.
+    ///
+    /// LMDB allows a thread only one transaction, so it holds the reader
+    /// on another thread.  And it can't grow its map while any transaction
+    /// is open, so during `f` it caps the map at a few gigabytes more than
+    /// the data it already holds, and writes fail with `Full` once they fill it.
+    fn with_reader<T, F: FnOnce() -> T>(&self, f: F) -> T;
+}
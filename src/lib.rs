@@ -0,0 +1,30 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Code shared by the kvbench benches and programs.
+
+extern crate db_key;
+extern crate leveldb;
+extern crate libc;
+extern crate lmdb;
+extern crate lmdb_sys;
+
+pub mod engine;
+pub mod map_size;
+pub mod multimap;
+pub mod page_cache;
+pub mod procfs;
+pub mod rusage;
+pub mod tables;
+pub mod verify;
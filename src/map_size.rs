@@ -0,0 +1,111 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Management of the size of LMDB's memory map, which limits the size
+//! of a datastore.  A write that needs more space than the map has
+//! fails with Error::MapFull, after which we can grow the map and retry it,
+//! as any application that doesn't know in advance how large its datastore
+//! will get has to do.
+
+use libc;
+
+use lmdb::{
+    Environment,
+    Error,
+    RwTransaction,
+    Transaction,
+};
+
+use lmdb_sys::{
+    mdb_env_info,
+    mdb_env_set_mapsize,
+    MDB_envinfo,
+};
+
+use std::mem;
+
+/// The current size of the environment's map.
+pub fn get(env: &Environment) -> usize {
+    unsafe {
+        let mut info: MDB_envinfo = mem::zeroed();
+        match mdb_env_info(env.env(), &mut info) {
+            0 => info.me_mapsize,
+            err => panic!("{}", Error::from_err_code(err)),
+        }
+    }
+}
+
+/// The size of the data in the environment's map, i.e. the pages it has used.
+pub fn used(env: &Environment) -> usize {
+    let page_size = env.stat().unwrap().page_size() as usize;
+    unsafe {
+        let mut info: MDB_envinfo = mem::zeroed();
+        match mdb_env_info(env.env(), &mut info) {
+            0 => (info.me_last_pgno + 1) * page_size,
+            err => panic!("{}", Error::from_err_code(err)),
+        }
+    }
+}
+
+/// Set the size of the environment's map, rounded up to a multiple of the
+/// system page size.  LMDB raises the size to that of the data in the map if
+/// it's smaller.  The process must not have a transaction open in the environment.
+pub fn set(env: &Environment, size: usize) -> Result<(), Error> {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let size = size.div_ceil(page_size) * page_size;
+    match unsafe { mdb_env_set_mapsize(env.env(), size) } {
+        0 => Ok(()),
+        err => Err(Error::from_err_code(err)),
+    }
+}
+
+/// Double the size of the environment's map.
+pub fn grow(env: &Environment) {
+    let size = get(env);
+    set(env, size * 2).unwrap();
+}
+
+/// Run `f` in a write transaction and commit it, returning its error
+/// (including Error::MapFull) rather than growing the map, as we must
+/// while another thread holds a transaction open in the environment.
+pub fn try_write<F>(env: &Environment, f: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut RwTransaction) -> Result<(), Error>,
+{
+    let mut txn = env.begin_rw_txn()?;
+    // Dropping the transaction when `f` fails aborts it.
+    f(&mut txn).and_then(|()| txn.commit())
+}
+
+/// Run `f` in a write transaction and commit it.  If the map fills up,
+/// abort the transaction, grow the map, and run `f` again in a new one,
+/// so `f` must be idempotent.  Returns the number of times we grew the map.
+pub fn write<F>(env: &Environment, mut f: F) -> u32
+where
+    F: FnMut(&mut RwTransaction) -> Result<(), Error>,
+{
+    let mut resizes = 0;
+    loop {
+        let mut txn = env.begin_rw_txn().unwrap();
+        // Dropping the transaction when `f` fails aborts it.
+        match f(&mut txn).and_then(|()| txn.commit()) {
+            Ok(()) => return resizes,
+            Err(Error::MapFull) => {
+                grow(env);
+                resizes += 1;
+            },
+            Err(err) => panic!("{}", err),
+        }
+    }
+}
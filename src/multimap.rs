@@ -0,0 +1,301 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Layouts of a datastore that maps each key to many values, like
+//! a secondary index that maps each value of an indexed field to the primary
+//! keys of the records with that value.  LMDB supports multiple values
+//! per key natively, in databases with the DUP_SORT flag, which stores
+//! the values of each key in sorted order (and the DUP_FIXED flag, which
+//! additionally requires them to be the same size).  In LevelDB, we store
+//! each pair as a composite key, with an empty value, so a key's values
+//! are the keys in the range that starts with it.
+
+use db_key::Key;
+
+use leveldb::database::batch::{
+    Batch,
+    Writebatch,
+};
+use leveldb::database::Database as LeveldbDatabase;
+use leveldb::iterator::{
+    Iterable,
+    LevelDBIterator,
+};
+use leveldb::options::{
+    Options,
+    ReadOptions,
+    WriteOptions,
+};
+
+use lmdb::{
+    Cursor,
+    Database,
+    DatabaseFlags,
+    Environment,
+    Transaction,
+    WriteFlags,
+};
+
+use map_size;
+
+use std::path::Path;
+
+pub trait Multimap: Sized {
+    /// The name of the layout, as it appears in benchmark names.
+    const NAME: &'static str;
+
+    /// Open the datastore in the given directory, creating it if missing.
+    /// Writes are durable once they return, as with Engine::open's sync mode.
+    fn open(path: &Path) -> Self;
+
+    /// Insert the given (key, value) pairs in a single transaction (LMDB)
+    /// or batch (LevelDB).
+    fn insert(&self, pairs: &[(u32, u32)]);
+
+    /// Get the first (i.e. smallest) value of each of the given keys, which
+    /// must all exist, in a single transaction (LMDB), returning their sum.
+    fn get(&self, keys: &[u32]) -> u64;
+
+    /// Iterate over the values of each of the given keys, which must all
+    /// exist, in a single transaction (LMDB), returning the number of values.
+    fn values(&self, keys: &[u32]) -> usize;
+
+    /// Delete the given keys and all their values in a single transaction
+    /// (LMDB) or batch (LevelDB).
+    fn delete(&self, keys: &[u32]);
+
+    /// Get every (key, value) pair, in key and then value order.
+    fn pairs(&self) -> Vec<(u32, u32)>;
+}
+
+// The LMDB layouts, which differ only in the flags of their database.
+struct LmdbDups {
+    env: Environment,
+    db: Database,
+}
+
+impl LmdbDups {
+    fn open(path: &Path, flags: DatabaseFlags) -> LmdbDups {
+        let env = Environment::new().open(path).unwrap();
+        let db = env.create_db(None, flags).unwrap();
+        LmdbDups { env, db }
+    }
+
+    fn insert(&self, pairs: &[(u32, u32)]) {
+        map_size::write(&self.env, |txn| {
+            for (key, value) in pairs {
+                txn.put(self.db, &key.to_be_bytes(), &value.to_be_bytes(), WriteFlags::empty())?;
+            }
+            Ok(())
+        });
+    }
+
+    fn get(&self, keys: &[u32]) -> u64 {
+        let txn = self.env.begin_ro_txn().unwrap();
+        keys.iter().map(|key| u64::from(from_bytes(txn.get(self.db, &key.to_be_bytes()).unwrap()))).sum()
+    }
+
+    fn values(&self, keys: &[u32]) -> usize {
+        let txn = self.env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(self.db).unwrap();
+        keys.iter().map(|key| cursor.iter_dup_of(&key.to_be_bytes()).unwrap().count()).sum()
+    }
+
+    fn delete(&self, keys: &[u32]) {
+        map_size::write(&self.env, |txn| {
+            for key in keys {
+                // Deleting a key without specifying a value deletes all its values.
+                txn.del(self.db, &key.to_be_bytes(), None)?;
+            }
+            Ok(())
+        });
+    }
+
+    fn pairs(&self) -> Vec<(u32, u32)> {
+        let txn = self.env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(self.db).unwrap();
+        cursor.iter().map(|(key, value)| (from_bytes(key), from_bytes(value))).collect()
+    }
+}
+
+fn from_bytes(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// A DUP_SORT database in LMDB.
+pub struct LmdbDupSort(LmdbDups);
+
+impl Multimap for LmdbDupSort {
+    const NAME: &'static str = "lmdb_dup_sort";
+
+    fn open(path: &Path) -> LmdbDupSort {
+        LmdbDupSort(LmdbDups::open(path, DatabaseFlags::DUP_SORT))
+    }
+
+    fn insert(&self, pairs: &[(u32, u32)]) {
+        self.0.insert(pairs)
+    }
+
+    fn get(&self, keys: &[u32]) -> u64 {
+        self.0.get(keys)
+    }
+
+    fn values(&self, keys: &[u32]) -> usize {
+        self.0.values(keys)
+    }
+
+    fn delete(&self, keys: &[u32]) {
+        self.0.delete(keys)
+    }
+
+    fn pairs(&self) -> Vec<(u32, u32)> {
+        self.0.pairs()
+    }
+}
+
+/// A DUP_SORT database in LMDB whose values are all the same size.
+pub struct LmdbDupFixed(LmdbDups);
+
+impl Multimap for LmdbDupFixed {
+    const NAME: &'static str = "lmdb_dup_fixed";
+
+    fn open(path: &Path) -> LmdbDupFixed {
+        LmdbDupFixed(LmdbDups::open(path, DatabaseFlags::DUP_SORT | DatabaseFlags::DUP_FIXED))
+    }
+
+    fn insert(&self, pairs: &[(u32, u32)]) {
+        self.0.insert(pairs)
+    }
+
+    fn get(&self, keys: &[u32]) -> u64 {
+        self.0.get(keys)
+    }
+
+    fn values(&self, keys: &[u32]) -> usize {
+        self.0.values(keys)
+    }
+
+    fn delete(&self, keys: &[u32]) {
+        self.0.delete(keys)
+    }
+
+    fn pairs(&self) -> Vec<(u32, u32)> {
+        self.0.pairs()
+    }
+}
+
+// A key and one of its values, which LevelDB sorts by key and then value.
+struct CompositeKey {
+    key: u32,
+    value: u32,
+}
+
+impl Key for CompositeKey {
+    fn from_u8(bytes: &[u8]) -> CompositeKey {
+        assert_eq!(bytes.len(), 8);
+        CompositeKey {
+            key: from_bytes(&bytes[..4]),
+            value: from_bytes(&bytes[4..]),
+        }
+    }
+
+    fn as_slice<T, F: Fn(&[u8]) -> T>(&self, f: F) -> T {
+        let mut bytes = [0u8; 8];
+        bytes[..4].copy_from_slice(&self.key.to_be_bytes());
+        bytes[4..].copy_from_slice(&self.value.to_be_bytes());
+        f(&bytes)
+    }
+}
+
+/// A LevelDB database of composite keys.
+pub struct LeveldbComposite {
+    db: LeveldbDatabase<CompositeKey>,
+}
+
+impl LeveldbComposite {
+    // Iterate over the values of the given key.
+    fn iter_values<'a>(&'a self, key: u32, start: &'a CompositeKey) -> impl Iterator<Item = u32> + 'a {
+        self.db
+            .keys_iter(ReadOptions::new())
+            .from(start)
+            .take_while(move |composite| composite.key == key)
+            .map(|composite| composite.value)
+    }
+
+    fn write_options(&self) -> WriteOptions {
+        // LevelDB writes are async by default.  Set WriteOptions::sync
+        // to true to make them sync, like LMDB's.
+        let mut write_opts = WriteOptions::new();
+        write_opts.sync = true;
+        write_opts
+    }
+}
+
+impl Multimap for LeveldbComposite {
+    const NAME: &'static str = "leveldb_composite";
+
+    fn open(path: &Path) -> LeveldbComposite {
+        let mut options = Options::new();
+        options.create_if_missing = true;
+        LeveldbComposite {
+            db: LeveldbDatabase::open(path, options).unwrap(),
+        }
+    }
+
+    fn insert(&self, pairs: &[(u32, u32)]) {
+        let batch = &mut Writebatch::new();
+        for &(key, value) in pairs {
+            batch.put(CompositeKey { key, value }, &[]);
+        }
+        self.db.write(self.write_options(), batch).unwrap();
+    }
+
+    fn get(&self, keys: &[u32]) -> u64 {
+        keys.iter()
+            .map(|&key| {
+                let start = CompositeKey { key, value: 0 };
+                let value = self.iter_values(key, &start).next();
+                u64::from(value.unwrap())
+            })
+            .sum()
+    }
+
+    fn values(&self, keys: &[u32]) -> usize {
+        keys.iter()
+            .map(|&key| {
+                let start = CompositeKey { key, value: 0 };
+                let count = self.iter_values(key, &start).count();
+                assert!(count > 0);
+                count
+            })
+            .sum()
+    }
+
+    // LevelDB can't delete a range of keys, so we have to find the composite
+    // keys for each key's values and delete them individually.
+    fn delete(&self, keys: &[u32]) {
+        let batch = &mut Writebatch::new();
+        for &key in keys {
+            let start = CompositeKey { key, value: 0 };
+            for value in self.iter_values(key, &start) {
+                batch.delete(CompositeKey { key, value });
+            }
+        }
+        self.db.write(self.write_options(), batch).unwrap();
+    }
+
+    fn pairs(&self) -> Vec<(u32, u32)> {
+        self.db.keys_iter(ReadOptions::new()).map(|composite| (composite.key, composite.value)).collect()
+    }
+}
@@ -0,0 +1,55 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Eviction of datastore files from the operating system's page cache,
+//! so benchmarks can measure reads that actually go to disk.
+
+use libc;
+
+use std::fs::{
+    self,
+    File,
+};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+/// Evict the files in the given directory from the page cache.
+///
+/// The datastore must be closed first, since the kernel won't evict pages
+/// that are still mapped into a process (which LMDB's pages always are
+/// while its environment is open).
+pub fn evict(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            evict_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn evict_file(path: &Path) -> io::Result<()> {
+    let file = File::open(path)?;
+
+    // The kernel only drops clean pages, so flush dirty ones to disk first.
+    file.sync_all()?;
+
+    // A length of zero means "through the end of the file."
+    let result = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+    Ok(())
+}
@@ -0,0 +1,154 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Readers for the statistics Linux reports about the current process
+//! in its /proc/self directory.
+
+use std::fs::{
+    File,
+    OpenOptions,
+};
+use std::io::{
+    self,
+    BufRead,
+    BufReader,
+    Write,
+};
+use std::path::Path;
+
+/// The memory footprint of the current process, in kilobytes.
+#[derive(Debug)]
+pub struct Memory {
+    /// The peak resident set size (VmHWM).
+    pub peak_rss: u64,
+    /// The current resident set size (VmRSS).
+    pub rss: u64,
+    /// The portion of the resident set that is mapped from files
+    /// in a given directory, like LMDB's data.mdb or LevelDB's tables.
+    pub mapped_rss: u64,
+}
+
+/// Get the memory footprint of the current process, attributing
+/// the resident pages of files mapped from `dir` to `mapped_rss`.
+pub fn memory(dir: &Path) -> io::Result<Memory> {
+    let mut memory = Memory {
+        peak_rss: 0,
+        rss: 0,
+        mapped_rss: 0,
+    };
+
+    for line in BufReader::new(File::open("/proc/self/status")?).lines() {
+        let line = line?;
+        if line.starts_with("VmHWM:") {
+            memory.peak_rss = parse_kb(&line);
+        } else if line.starts_with("VmRSS:") {
+            memory.rss = parse_kb(&line);
+        }
+    }
+
+    // The smaps file lists every mapping in the process, each of which begins
+    // with a header like "7f1c2a000000-7f1c2a500000 rw-s 00000000 08:01 1234
+    // /path/to/file" followed by fields like "Rss:  1024 kB".
+    let dir = dir.to_string_lossy();
+    let mut in_dir = false;
+    for line in BufReader::new(File::open("/proc/self/smaps")?).lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("Rss:") if in_dir => memory.mapped_rss += parse_kb(&line),
+            Some(field) if field.ends_with(':') => {},
+            Some(_) => in_dir = fields.nth(4).is_some_and(|path| path.starts_with(&*dir)),
+            None => {},
+        }
+    }
+
+    Ok(memory)
+}
+
+/// Reset the peak resident set size (VmHWM) of the current process
+/// to its current resident set size, so it only reflects later allocations.
+pub fn reset_peak_rss() -> io::Result<()> {
+    OpenOptions::new().write(true).open("/proc/self/clear_refs")?.write_all(b"5")
+}
+
+// Parse the number of kilobytes from a line like "VmRSS:   1234 kB".
+fn parse_kb(line: &str) -> u64 {
+    line.split_whitespace().nth(1).and_then(|kb| kb.parse().ok()).unwrap_or(0)
+}
+
+/// The I/O the current process has performed, as reported by /proc/self/io.
+/// The "char" counts include reads and writes satisfied by the page cache,
+/// while the "bytes" counts only include those that reached the storage layer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IoCounters {
+    /// Bytes read by read-like system calls.
+    pub rchar: u64,
+    /// Bytes written by write-like system calls.
+    pub wchar: u64,
+    /// Read-like system calls.
+    pub syscr: u64,
+    /// Write-like system calls.
+    pub syscw: u64,
+    /// Bytes fetched from the storage layer.
+    pub read_bytes: u64,
+    /// Bytes sent to the storage layer.
+    pub write_bytes: u64,
+    /// Bytes that were counted in `write_bytes` but never written, because
+    /// the file they were written to was truncated or deleted first.
+    pub cancelled_write_bytes: u64,
+}
+
+impl IoCounters {
+    /// The I/O performed between the `earlier` reading and this one.
+    pub fn since(&self, earlier: &IoCounters) -> IoCounters {
+        IoCounters {
+            rchar: self.rchar - earlier.rchar,
+            wchar: self.wchar - earlier.wchar,
+            syscr: self.syscr - earlier.syscr,
+            syscw: self.syscw - earlier.syscw,
+            read_bytes: self.read_bytes - earlier.read_bytes,
+            write_bytes: self.write_bytes - earlier.write_bytes,
+            cancelled_write_bytes: self.cancelled_write_bytes - earlier.cancelled_write_bytes,
+        }
+    }
+
+    /// The bytes that were actually written to the storage layer.
+    pub fn physical_write_bytes(&self) -> u64 {
+        self.write_bytes.saturating_sub(self.cancelled_write_bytes)
+    }
+}
+
+/// Get the I/O the current process (including all of its threads, like
+/// LevelDB's compaction thread) has performed since it started.
+pub fn io_counters() -> io::Result<IoCounters> {
+    let mut counters = IoCounters::default();
+
+    for line in BufReader::new(File::open("/proc/self/io")?).lines() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        let field = match fields.next() {
+            Some("rchar:") => &mut counters.rchar,
+            Some("wchar:") => &mut counters.wchar,
+            Some("syscr:") => &mut counters.syscr,
+            Some("syscw:") => &mut counters.syscw,
+            Some("read_bytes:") => &mut counters.read_bytes,
+            Some("write_bytes:") => &mut counters.write_bytes,
+            Some("cancelled_write_bytes:") => &mut counters.cancelled_write_bytes,
+            _ => continue,
+        };
+        *field = fields.next().and_then(|value| value.parse().ok()).unwrap_or(0);
+    }
+
+    Ok(counters)
+}
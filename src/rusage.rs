@@ -0,0 +1,98 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Resource usage statistics from getrusage(2), which break down the time
+//! a workload takes into CPU time spent in user space and in the kernel,
+//! and count the page faults and context switches it incurs.
+
+use libc;
+
+use std::mem;
+use std::time::Duration;
+
+/// The resources used by the current process or thread.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Usage {
+    /// Total CPU time, from the process's (or thread's) CPU-time clock.
+    ///
+    /// The kernel measures this precisely, whereas it apportions it between
+    /// `user_time` and `system_time` by sampling, and it samples threads and
+    /// processes independently.  So over short intervals, only this total
+    /// can be meaningfully compared between a thread and its process.
+    pub cpu_time: Duration,
+    /// CPU time spent executing in user space.
+    pub user_time: Duration,
+    /// CPU time spent executing in the kernel.
+    pub system_time: Duration,
+    /// Page faults serviced without any I/O.
+    pub minor_faults: u64,
+    /// Page faults that required I/O.
+    pub major_faults: u64,
+    /// Context switches because the process waited for a resource, like I/O.
+    pub voluntary_switches: u64,
+    /// Context switches because the process's time slice expired or
+    /// a higher-priority process became runnable.
+    pub involuntary_switches: u64,
+}
+
+impl Usage {
+    /// The resources used between the `earlier` reading and this one.
+    pub fn since(&self, earlier: &Usage) -> Usage {
+        Usage {
+            cpu_time: self.cpu_time.checked_sub(earlier.cpu_time).unwrap_or_default(),
+            user_time: self.user_time.checked_sub(earlier.user_time).unwrap_or_default(),
+            system_time: self.system_time.checked_sub(earlier.system_time).unwrap_or_default(),
+            minor_faults: self.minor_faults.saturating_sub(earlier.minor_faults),
+            major_faults: self.major_faults.saturating_sub(earlier.major_faults),
+            voluntary_switches: self.voluntary_switches.saturating_sub(earlier.voluntary_switches),
+            involuntary_switches: self.involuntary_switches.saturating_sub(earlier.involuntary_switches),
+        }
+    }
+}
+
+/// The resources used by every thread in the current process, including
+/// threads that have exited, like background threads the engines start.
+pub fn process() -> Usage {
+    getrusage(libc::RUSAGE_SELF, libc::CLOCK_PROCESS_CPUTIME_ID)
+}
+
+/// The resources used by the calling thread alone.  Subtracting this from
+/// `process()` yields the resources used by the process's other threads.
+pub fn thread() -> Usage {
+    getrusage(libc::RUSAGE_THREAD, libc::CLOCK_THREAD_CPUTIME_ID)
+}
+
+fn getrusage(who: libc::c_int, clock: libc::clockid_t) -> Usage {
+    let mut usage: libc::rusage = unsafe { mem::zeroed() };
+    let result = unsafe { libc::getrusage(who, &mut usage) };
+    assert_eq!(result, 0, "getrusage failed");
+
+    let mut cpu_time: libc::timespec = unsafe { mem::zeroed() };
+    let result = unsafe { libc::clock_gettime(clock, &mut cpu_time) };
+    assert_eq!(result, 0, "clock_gettime failed");
+
+    Usage {
+        cpu_time: Duration::new(cpu_time.tv_sec as u64, cpu_time.tv_nsec as u32),
+        user_time: to_duration(usage.ru_utime),
+        system_time: to_duration(usage.ru_stime),
+        minor_faults: usage.ru_minflt as u64,
+        major_faults: usage.ru_majflt as u64,
+        voluntary_switches: usage.ru_nvcsw as u64,
+        involuntary_switches: usage.ru_nivcsw as u64,
+    }
+}
+
+fn to_duration(time: libc::timeval) -> Duration {
+    Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1000)
+}
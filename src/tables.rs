@@ -0,0 +1,300 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Layouts of a datastore that spreads its pairs across several tables,
+//! like an application that stores each type of data in its own table.
+//! LMDB supports tables natively, as named databases in an environment,
+//! whereas LevelDB only has a single key space per database, so an
+//! application must either prefix each key with its table or store
+//! each table in a separate database.
+
+use leveldb::database::batch::{
+    Batch,
+    Writebatch,
+};
+use leveldb::database::Database as LeveldbDatabase;
+use leveldb::iterator::Iterable;
+use leveldb::kv::KV;
+use leveldb::options::{
+    Options,
+    ReadOptions,
+    WriteOptions,
+};
+
+use lmdb::{
+    Cursor,
+    Database,
+    DatabaseFlags,
+    Environment,
+    Error,
+    Transaction,
+    WriteFlags,
+};
+
+use map_size;
+
+use std::path::Path;
+
+pub trait Tables: Sized {
+    /// The name of the layout, as it appears in benchmark names.
+    const NAME: &'static str;
+
+    /// Open the datastore with the given number of tables in the given
+    /// directory, creating it if missing.  Writes are durable once `write`
+    /// returns, as with Engine::open's sync mode.
+    fn open(path: &Path, num_tables: u32) -> Self;
+
+    /// Write the given (table, key, value) triples, in a single transaction
+    /// (LMDB) or batch (LevelDB) if the layout allows it.
+    fn write(&self, pairs: &[(u32, u32, Vec<u8>)]);
+
+    /// Read the values of the given (table, key) pairs, which must all exist,
+    /// in a single transaction (LMDB), returning the total size of the values.
+    fn read(&self, keys: &[(u32, u32)]) -> usize;
+
+    /// Get the value of the given key in the given table, if it exists.
+    fn get(&self, table: u32, key: u32) -> Option<Vec<u8>>;
+
+    /// Get every (table, key, value) triple, in table and then key order.
+    fn pairs(&self) -> Vec<(u32, u32, Vec<u8>)>;
+}
+
+// Prefix a key with its table, which we store in the key's high byte,
+// so keys must be less than 2^24 and tables less than 2^7 (the latter
+// so the LevelDB keys, which are i32, remain positive and thus sort
+// in the same order as the LMDB keys).
+fn prefix_key(table: u32, key: u32) -> u32 {
+    assert!(table < 1 << 7 && key < 1 << 24);
+    table << 24 | key
+}
+
+fn unprefix_key(key: u32) -> (u32, u32) {
+    (key >> 24, key & ((1 << 24) - 1))
+}
+
+fn from_bytes(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn get_lmdb<T: Transaction>(txn: &T, db: Database, key: u32) -> Option<Vec<u8>> {
+    match txn.get(db, &key.to_be_bytes()) {
+        Ok(value) => Some(value.to_vec()),
+        Err(Error::NotFound) => None,
+        Err(err) => panic!("{}", err),
+    }
+}
+
+fn open_lmdb(path: &Path, max_dbs: u32) -> Environment {
+    let mut builder = Environment::new();
+    builder.set_max_dbs(max_dbs);
+    builder.open(path).unwrap()
+}
+
+fn open_leveldb(path: &Path) -> LeveldbDatabase<i32> {
+    let mut options = Options::new();
+    options.create_if_missing = true;
+    LeveldbDatabase::open(path, options).unwrap()
+}
+
+fn leveldb_write_options() -> WriteOptions {
+    // LevelDB writes are async by default.  Set WriteOptions::sync
+    // to true to make them sync, like LMDB's.
+    let mut write_opts = WriteOptions::new();
+    write_opts.sync = true;
+    write_opts
+}
+
+/// Each table is a named database in a single LMDB environment.
+pub struct LmdbDatabases {
+    env: Environment,
+    dbs: Vec<Database>,
+}
+
+impl Tables for LmdbDatabases {
+    const NAME: &'static str = "lmdb_databases";
+
+    fn open(path: &Path, num_tables: u32) -> LmdbDatabases {
+        let env = open_lmdb(path, num_tables);
+        let dbs = (0..num_tables)
+            .map(|table| env.create_db(Some(&format!("table{}", table)), DatabaseFlags::empty()).unwrap())
+            .collect();
+        LmdbDatabases { env, dbs }
+    }
+
+    fn write(&self, pairs: &[(u32, u32, Vec<u8>)]) {
+        map_size::write(&self.env, |txn| {
+            for (table, key, value) in pairs {
+                txn.put(self.dbs[*table as usize], &key.to_be_bytes(), value, WriteFlags::empty())?;
+            }
+            Ok(())
+        });
+    }
+
+    fn read(&self, keys: &[(u32, u32)]) -> usize {
+        let txn = self.env.begin_ro_txn().unwrap();
+        keys.iter().map(|&(table, key)| txn.get(self.dbs[table as usize], &key.to_be_bytes()).unwrap().len()).sum()
+    }
+
+    fn get(&self, table: u32, key: u32) -> Option<Vec<u8>> {
+        get_lmdb(&self.env.begin_ro_txn().unwrap(), self.dbs[table as usize], key)
+    }
+
+    fn pairs(&self) -> Vec<(u32, u32, Vec<u8>)> {
+        let txn = self.env.begin_ro_txn().unwrap();
+        let mut pairs = vec![];
+        for (table, &db) in self.dbs.iter().enumerate() {
+            let mut cursor = txn.open_ro_cursor(db).unwrap();
+            pairs.extend(cursor.iter().map(|(key, value)| (table as u32, from_bytes(key), value.to_vec())));
+        }
+        pairs
+    }
+}
+
+/// Tables share LMDB's unnamed database, and each key is prefixed
+/// with its table.
+pub struct LmdbPrefixes {
+    env: Environment,
+    db: Database,
+}
+
+impl Tables for LmdbPrefixes {
+    const NAME: &'static str = "lmdb_prefixes";
+
+    fn open(path: &Path, _num_tables: u32) -> LmdbPrefixes {
+        let env = open_lmdb(path, 0);
+        let db = env.open_db(None).unwrap();
+        LmdbPrefixes { env, db }
+    }
+
+    fn write(&self, pairs: &[(u32, u32, Vec<u8>)]) {
+        map_size::write(&self.env, |txn| {
+            for (table, key, value) in pairs {
+                txn.put(self.db, &prefix_key(*table, *key).to_be_bytes(), value, WriteFlags::empty())?;
+            }
+            Ok(())
+        });
+    }
+
+    fn read(&self, keys: &[(u32, u32)]) -> usize {
+        let txn = self.env.begin_ro_txn().unwrap();
+        keys.iter().map(|&(table, key)| txn.get(self.db, &prefix_key(table, key).to_be_bytes()).unwrap().len()).sum()
+    }
+
+    fn get(&self, table: u32, key: u32) -> Option<Vec<u8>> {
+        get_lmdb(&self.env.begin_ro_txn().unwrap(), self.db, prefix_key(table, key))
+    }
+
+    fn pairs(&self) -> Vec<(u32, u32, Vec<u8>)> {
+        let txn = self.env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(self.db).unwrap();
+        cursor
+            .iter()
+            .map(|(key, value)| {
+                let (table, key) = unprefix_key(from_bytes(key));
+                (table, key, value.to_vec())
+            })
+            .collect()
+    }
+}
+
+/// Tables share a single LevelDB database, and each key is prefixed
+/// with its table.
+pub struct LeveldbPrefixes {
+    db: LeveldbDatabase<i32>,
+}
+
+impl Tables for LeveldbPrefixes {
+    const NAME: &'static str = "leveldb_prefixes";
+
+    fn open(path: &Path, _num_tables: u32) -> LeveldbPrefixes {
+        LeveldbPrefixes {
+            db: open_leveldb(path),
+        }
+    }
+
+    fn write(&self, pairs: &[(u32, u32, Vec<u8>)]) {
+        let batch = &mut Writebatch::new();
+        for (table, key, value) in pairs {
+            batch.put(prefix_key(*table, *key) as i32, value);
+        }
+        self.db.write(leveldb_write_options(), batch).unwrap();
+    }
+
+    fn read(&self, keys: &[(u32, u32)]) -> usize {
+        keys.iter()
+            .map(|&(table, key)| {
+                self.db.get(ReadOptions::new(), prefix_key(table, key) as i32).unwrap().unwrap().len()
+            })
+            .sum()
+    }
+
+    fn get(&self, table: u32, key: u32) -> Option<Vec<u8>> {
+        self.db.get(ReadOptions::new(), prefix_key(table, key) as i32).unwrap()
+    }
+
+    fn pairs(&self) -> Vec<(u32, u32, Vec<u8>)> {
+        self.db
+            .iter(ReadOptions::new())
+            .map(|(key, value)| {
+                let (table, key) = unprefix_key(key as u32);
+                (table, key, value)
+            })
+            .collect()
+    }
+}
+
+/// Each table is a separate LevelDB database in its own directory, so
+/// writing to several tables takes a batch per table, and the batches
+/// aren't atomic with respect to each other.
+pub struct LeveldbDirectories {
+    dbs: Vec<LeveldbDatabase<i32>>,
+}
+
+impl Tables for LeveldbDirectories {
+    const NAME: &'static str = "leveldb_directories";
+
+    fn open(path: &Path, num_tables: u32) -> LeveldbDirectories {
+        LeveldbDirectories {
+            dbs: (0..num_tables).map(|table| open_leveldb(&path.join(format!("table{}", table)))).collect(),
+        }
+    }
+
+    fn write(&self, pairs: &[(u32, u32, Vec<u8>)]) {
+        let mut batches: Vec<Writebatch<i32>> = self.dbs.iter().map(|_| Writebatch::new()).collect();
+        for (table, key, value) in pairs {
+            batches[*table as usize].put(*key as i32, value);
+        }
+        for (db, batch) in self.dbs.iter().zip(&batches) {
+            db.write(leveldb_write_options(), batch).unwrap();
+        }
+    }
+
+    fn read(&self, keys: &[(u32, u32)]) -> usize {
+        keys.iter()
+            .map(|&(table, key)| self.dbs[table as usize].get(ReadOptions::new(), key as i32).unwrap().unwrap().len())
+            .sum()
+    }
+
+    fn get(&self, table: u32, key: u32) -> Option<Vec<u8>> {
+        self.dbs[table as usize].get(ReadOptions::new(), key as i32).unwrap()
+    }
+
+    fn pairs(&self) -> Vec<(u32, u32, Vec<u8>)> {
+        let mut pairs = vec![];
+        for (table, db) in self.dbs.iter().enumerate() {
+            pairs.extend(db.iter(ReadOptions::new()).map(|(key, value)| (table as u32, key as u32, value)));
+        }
+        pairs
+    }
+}
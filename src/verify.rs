@@ -0,0 +1,100 @@
+// Copyright 2018 Mozilla
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Verification of the data the storage engines return.  The benches record
+//! a checksum of every value they write and, if the KVBENCH_VERIFY environment
+//! variable is set, check the keys, values, and number of pairs in the datastore
+//! after they finish measuring it, panicking if an engine returns wrong data.
+
+use std::collections::btree_map::Keys;
+use std::collections::BTreeMap;
+use std::env;
+
+/// Whether the benches should verify their datastores.
+pub fn enabled() -> bool {
+    env::var_os("KVBENCH_VERIFY").is_some()
+}
+
+/// The 64-bit FNV-1a hash of a value, which is fast enough to compute
+/// for every value we write and sensitive to any change in its bytes.
+pub fn checksum(value: &[u8]) -> u64 {
+    value.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3))
+}
+
+/// The checksums of the values written to a datastore, by key.
+#[derive(Default)]
+pub struct Checksums {
+    checksums: BTreeMap<u32, u64>,
+}
+
+impl Checksums {
+    pub fn new() -> Checksums {
+        Checksums::default()
+    }
+
+    /// Record the value written to a key, replacing any previous value.
+    pub fn insert(&mut self, key: u32, value: &[u8]) {
+        self.checksums.insert(key, checksum(value));
+    }
+
+    /// Record that a key was deleted.
+    pub fn remove(&mut self, key: u32) {
+        self.checksums.remove(&key);
+    }
+
+    /// The keys written to the datastore, in order.
+    pub fn keys(&self) -> Keys<'_, u32, u64> {
+        self.checksums.keys()
+    }
+
+    /// Check the value that the engine `name` returned for a key.
+    pub fn check_value(&self, name: &str, key: u32, value: Option<&[u8]>) {
+        match (self.checksums.get(&key), value) {
+            (Some(&expected), Some(value)) => {
+                assert_eq!(checksum(value), expected, "{} returned the wrong value for key {}", name, key)
+            },
+            (Some(_), None) => panic!("{} lost key {}", name, key),
+            (None, Some(_)) => panic!("{} returned a value for key {}, which was never written", name, key),
+            (None, None) => {},
+        }
+    }
+
+    /// Check the pairs that the engine `name` returned when iterating over
+    /// its datastore, which should be exactly the pairs written, in key order.
+    pub fn check_pairs<I, V>(&self, name: &str, pairs: I)
+    where
+        I: IntoIterator<Item = (u32, V)>,
+        V: AsRef<[u8]>,
+    {
+        let mut expected = self.checksums.iter();
+        let mut count = 0;
+        for (key, value) in pairs {
+            match expected.next() {
+                Some((&expected_key, &expected_checksum)) => {
+                    assert_eq!(key, expected_key, "{} iterated to the wrong key", name);
+                    assert_eq!(
+                        checksum(value.as_ref()),
+                        expected_checksum,
+                        "{} iterated to the wrong value for key {}",
+                        name,
+                        key
+                    );
+                },
+                None => panic!("{} iterated to key {}, which was never written", name, key),
+            }
+            count += 1;
+        }
+        assert_eq!(count, self.checksums.len(), "{} iterated over the wrong number of pairs", name);
+    }
+}